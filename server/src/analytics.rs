@@ -0,0 +1,231 @@
+// Statistics from the vote history ("y" command).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::{HistoryEvent, HistoryKind},
+    DatabaseData, DinnerId,
+};
+
+// Length of the periods vote shares and streaks are counted in, in seconds
+pub(crate) const WEEK: u64 = 7 * 24 * 60 * 60;
+
+// How a dinner has done
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DinnerStats {
+    pub(crate) id: DinnerId,
+    pub(crate) name: String,
+    // Number of times it was served
+    pub(crate) wins: usize,
+    // Number of votes it got, not counting ones taken back
+    pub(crate) votes: usize,
+    // Average stars
+    pub(crate) mean_rating: Option<f32>,
+    // When it was last served, in seconds since the Unix epoch
+    pub(crate) last_eaten: Option<u64>,
+}
+
+// A dinner's share of the votes in one week
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct SharePoint {
+    // Start of the week, in seconds since the Unix epoch
+    pub(crate) week: u64,
+    // Votes for this dinner that week
+    pub(crate) votes: usize,
+    // Fraction of all votes that week
+    pub(crate) share: f32,
+}
+
+// How much someone votes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VoterStats {
+    pub(crate) name: String,
+    // Number of votes cast
+    pub(crate) votes: usize,
+    // Weeks in a row they've voted, up to this week or last week
+    pub(crate) current_streak: usize,
+    // Most weeks in a row they've voted
+    pub(crate) longest_streak: usize,
+}
+
+// Statistics for the whole database
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Analytics {
+    // Most wins first
+    pub(crate) dinners: Vec<DinnerStats>,
+    // Most votes first
+    pub(crate) voters: Vec<VoterStats>,
+}
+
+// Statistics for one dinner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DinnerAnalytics {
+    #[serde(flatten)]
+    pub(crate) stats: DinnerStats,
+    // Oldest week first, only weeks with votes
+    pub(crate) share: Vec<SharePoint>,
+    // People who voted for it, most votes for it first
+    pub(crate) voters: Vec<VoterStats>,
+}
+
+impl DatabaseData {
+    // Votes that weren't taken back.  A dinner has one vote at a time, so an
+    // `Unvote` takes back the last vote for it.
+    fn votes(&self) -> impl Iterator<Item = &HistoryEvent> {
+        let mut last = HashMap::<DinnerId, usize>::new();
+        let mut taken_back = HashSet::new();
+        for (i, event) in self.history.iter().enumerate() {
+            match event.kind {
+                HistoryKind::Vote => {
+                    last.insert(event.dinner, i);
+                }
+                HistoryKind::Unvote => {
+                    taken_back.extend(last.remove(&event.dinner));
+                }
+                HistoryKind::Serve => {
+                    last.remove(&event.dinner);
+                }
+            }
+        }
+
+        self.history
+            .iter()
+            .enumerate()
+            .filter(move |(i, event)| {
+                event.kind == HistoryKind::Vote && !taken_back.contains(i)
+            })
+            .map(|(_, event)| event)
+    }
+
+    fn dinner_stats(&self, id: DinnerId) -> Option<DinnerStats> {
+        let dinner = self.dinners.get(&id)?;
+        let served = self.history.iter().filter(|event| {
+            event.kind == HistoryKind::Serve && event.dinner == id
+        });
+
+        Some(DinnerStats {
+            id,
+            name: dinner.name.clone(),
+            wins: served.clone().count(),
+            votes: self.votes().filter(|event| event.dinner == id).count(),
+            mean_rating: dinner.rating_summary().mean,
+            last_eaten: served.map(|event| event.at).max(),
+        })
+    }
+
+    // Count the votes of each person that match `filter`, most votes first.
+    fn voter_stats(
+        &self,
+        now: u64,
+        filter: impl Fn(&HistoryEvent) -> bool,
+    ) -> Vec<VoterStats> {
+        let mut counts = HashMap::<&str, usize>::new();
+        let mut weeks = HashMap::<&str, BTreeSet<u64>>::new();
+
+        for event in self.votes() {
+            weeks
+                .entry(&event.person)
+                .or_default()
+                .insert(event.at / WEEK);
+            if filter(event) {
+                *counts.entry(&event.person).or_default() += 1;
+            }
+        }
+
+        let mut voters: Vec<VoterStats> = counts
+            .into_iter()
+            .map(|(name, votes)| {
+                let (current_streak, longest_streak) =
+                    streaks(&weeks[name], now / WEEK);
+                VoterStats {
+                    name: name.to_string(),
+                    votes,
+                    current_streak,
+                    longest_streak,
+                }
+            })
+            .collect();
+        voters.sort_by(|a, b| b.votes.cmp(&a.votes).then(a.name.cmp(&b.name)));
+        voters
+    }
+
+    // Statistics for every dinner and person, as of `now`.
+    pub(crate) fn analytics(&self, now: u64) -> Analytics {
+        let mut dinners: Vec<DinnerStats> = self
+            .dinners
+            .keys()
+            .filter_map(|id| self.dinner_stats(*id))
+            .collect();
+        dinners.sort_by(|a, b| {
+            b.wins
+                .cmp(&a.wins)
+                .then(b.votes.cmp(&a.votes))
+                .then(a.name.cmp(&b.name))
+        });
+
+        Analytics {
+            dinners,
+            voters: self.voter_stats(now, |_| true),
+        }
+    }
+
+    // Statistics for one dinner as of `now`, `None` if it doesn't exist.
+    pub(crate) fn dinner_analytics(
+        &self,
+        index: &str,
+        now: u64,
+    ) -> Option<DinnerAnalytics> {
+        let id = self.find_dinner(index)?;
+        let stats = self.dinner_stats(id)?;
+        let mut weeks = BTreeMap::<u64, (usize, usize)>::new();
+
+        for event in self.votes() {
+            let (mine, all) = weeks.entry(event.at / WEEK).or_default();
+            *all += 1;
+            if event.dinner == id {
+                *mine += 1;
+            }
+        }
+
+        let share = weeks
+            .into_iter()
+            .map(|(week, (votes, all))| SharePoint {
+                week: week * WEEK,
+                votes,
+                share: votes as f32 / all as f32,
+            })
+            .collect();
+
+        Some(DinnerAnalytics {
+            stats,
+            share,
+            voters: self.voter_stats(now, |event| event.dinner == id),
+        })
+    }
+}
+
+// Current and longest runs of consecutive weeks.
+fn streaks(weeks: &BTreeSet<u64>, this_week: u64) -> (usize, usize) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous = None;
+
+    for &week in weeks {
+        run = match previous {
+            Some(previous) if previous + 1 == week => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(week);
+    }
+
+    // A streak is still going if the last vote was this week or last week
+    let current = match previous {
+        Some(last) if last + 1 >= this_week => run,
+        _ => 0,
+    };
+
+    (current, longest)
+}
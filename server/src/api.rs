@@ -0,0 +1,969 @@
+// JSON REST API, backed by the same database as the legacy POST protocol.
+
+use std::convert::Infallible;
+
+use async_std::io::ReadExt;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tide::{Body, Request, Response, Result, StatusCode};
+
+use crate::{
+    audit::Filter,
+    auth::{self, Secret},
+    history,
+    invites::{self, Invite},
+    outcome::{Missing, Outcome, Rejection},
+    photos,
+    ratings::RatingSummary,
+    roles::{check_permission, Permission, Role},
+    transfer::{Export, Format, Mode, Photos},
+    DbEvent, DinnerId, Server,
+};
+
+// Largest file accepted by `POST /api/admin/import`, in bytes
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+// A dinner option, as listed by `GET /api/dinners`
+#[derive(Serialize, Deserialize, Debug)]
+struct DinnerSummary {
+    // Dinner ID (the index used by every other route)
+    id: DinnerId,
+    name: String,
+    // Short description
+    short: String,
+    // Who voted for this one, if anyone
+    vote: Option<String>,
+}
+
+// A dinner option, as returned by `GET /api/dinners/:id`
+#[derive(Serialize, Deserialize, Debug)]
+struct DinnerDetails {
+    id: DinnerId,
+    name: String,
+    short: String,
+    long: String,
+    vote: Option<String>,
+    has_photo: bool,
+    ratings: RatingSummary,
+}
+
+// A person, as returned by `GET /api/people/:name`
+#[derive(Serialize, Deserialize, Debug)]
+struct PersonDetails {
+    name: String,
+    votes: u16,
+    // Whether they're an owner or admin, for apps from before roles
+    admin: bool,
+    role: Role,
+    // Whether they can log in yet
+    has_password: bool,
+    deactivated: bool,
+}
+
+// Body of requests that only need to know who is asking
+#[derive(Serialize, Deserialize, Debug)]
+struct UserRequest {
+    user: String,
+}
+
+// Query of requests with a binary body
+#[derive(Serialize, Deserialize, Debug)]
+struct UserQuery {
+    user: String,
+}
+
+// Body of `POST /api/dinners`
+#[derive(Serialize, Deserialize, Debug)]
+struct NewDinnerRequest {
+    user: String,
+    name: String,
+}
+
+// Body of `PATCH /api/dinners/:id`, every field but `user` is optional
+#[derive(Serialize, Deserialize, Debug)]
+struct EditDinnerRequest {
+    user: String,
+    // New dinner name
+    name: Option<String>,
+    // New short description
+    short: Option<String>,
+    // New long description
+    long: Option<String>,
+}
+
+// Body of `PUT /api/dinners/:id/rating`
+#[derive(Serialize, Deserialize, Debug)]
+struct RatingRequest {
+    user: String,
+    stars: u8,
+    note: Option<String>,
+}
+
+// Body of `POST /api/people`
+#[derive(Serialize, Deserialize, Debug)]
+struct NewPersonRequest {
+    name: String,
+    // Needed for everyone but the first account
+    #[serde(default)]
+    invite: Option<String>,
+    #[serde(flatten)]
+    secret: Secret,
+}
+
+// Body of `POST /api/login`
+#[derive(Serialize, Deserialize, Debug)]
+struct LoginRequest {
+    name: String,
+    // Password or PIN
+    password: String,
+}
+
+// Body of `POST /api/setup`
+#[derive(Serialize, Deserialize, Debug)]
+struct SetupRequest {
+    // Printed when the server started
+    token: String,
+    // Who becomes the owner
+    name: String,
+    #[serde(flatten)]
+    secret: Secret,
+}
+
+// Body of `PUT /api/people/:name/role`
+#[derive(Serialize, Deserialize, Debug)]
+struct RoleRequest {
+    user: String,
+    role: Role,
+}
+
+// Body of `PATCH /api/people/:name`
+#[derive(Serialize, Deserialize, Debug)]
+struct RenamePersonRequest {
+    user: String,
+    // New name
+    name: String,
+}
+
+// Body of `POST /api/invites`
+#[derive(Serialize, Deserialize, Debug)]
+struct NewInviteRequest {
+    user: String,
+    // Member if left out
+    #[serde(default)]
+    role: Option<Role>,
+    // `default_votes` if left out
+    #[serde(default)]
+    votes: Option<u16>,
+    // Accounts it makes, 1 if left out
+    #[serde(default)]
+    uses: Option<u32>,
+    // Seconds it lasts, forever if left out
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+// An invite, as returned by `GET /api/invites`
+#[derive(Serialize, Deserialize, Debug)]
+struct InviteDetails {
+    code: String,
+    #[serde(flatten)]
+    invite: Invite,
+}
+
+// Body of `PUT /api/people/:name/password`
+#[derive(Serialize, Deserialize, Debug)]
+struct PasswordRequest {
+    user: String,
+    #[serde(flatten)]
+    secret: Secret,
+}
+
+// Query of `GET /api/admin/export`
+#[derive(Serialize, Deserialize, Debug)]
+struct ExportQuery {
+    user: String,
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    photos: Photos,
+}
+
+// Query of `POST /api/admin/import`, the body is the file
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportQuery {
+    user: String,
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    mode: Mode,
+}
+
+// Why an import was refused
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportErrors {
+    errors: Vec<String>,
+}
+
+// Query of `GET /api/admin/audit`, leaving a filter out lists everything
+#[derive(Serialize, Deserialize, Debug)]
+struct AuditQuery {
+    user: String,
+    // Changes made by or to them
+    #[serde(default)]
+    person: Option<String>,
+    #[serde(default)]
+    dinner: Option<DinnerId>,
+    // Type of change, like `delete_dinner`
+    #[serde(default)]
+    action: Option<String>,
+    // Changes made from then, and before `to`, in seconds since the Unix
+    // epoch
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+}
+
+// Body of `PUT /api/votes`
+#[derive(Serialize, Deserialize, Debug)]
+struct SetVotesRequest {
+    user: String,
+    votes: u16,
+}
+
+// Register the API routes on the app.
+pub(crate) fn routes(app: &mut tide::Server<Server>) {
+    app.at("/api/dinners").get(list_dinners).post(new_dinner);
+    app.at("/api/dinners/:id")
+        .get(get_dinner)
+        .patch(edit_dinner)
+        .delete(delete_dinner);
+    app.at("/api/dinners/:id/votes").post(vote).delete(unvote);
+    app.at("/api/dinners/:id/photo")
+        .get(get_photo)
+        .put(set_photo)
+        .delete(delete_photo);
+    app.at("/api/dinners/:id/served").post(serve);
+    app.at("/api/dinners/:id/analytics")
+        .get(get_dinner_analytics);
+    app.at("/api/dinners/:id/rating")
+        .put(set_rating)
+        .delete(clear_rating);
+    app.at("/api/login").post(login);
+    app.at("/api/logout").post(logout);
+    app.at("/api/setup").post(setup);
+    app.at("/api/people").post(new_person);
+    app.at("/api/people/:name")
+        .get(get_person)
+        .patch(rename_person)
+        .delete(remove_person);
+    app.at("/api/people/:name/password").put(set_password);
+    app.at("/api/people/:name/role").put(set_role);
+    app.at("/api/people/:name/deactivate").post(deactivate);
+    app.at("/api/people/:name/reactivate").post(reactivate);
+    app.at("/api/people/:name/votes").get(get_votes);
+    app.at("/api/invites").get(list_invites).post(new_invite);
+    app.at("/api/invites/:code").delete(delete_invite);
+    app.at("/api/votes").put(set_votes);
+    app.at("/api/analytics").get(get_analytics);
+    app.at("/api/admin/backups")
+        .get(list_backups)
+        .post(create_backup);
+    app.at("/api/admin/backups/:name/restore")
+        .post(restore_backup);
+    app.at("/api/admin/export").get(export);
+    app.at("/api/admin/import").post(import);
+    app.at("/api/admin/audit").get(audit_log);
+}
+
+// Get a percent-decoded route parameter.
+fn param(request: &Request<Server>, key: &str) -> Result<String> {
+    let raw: String = request.param(key)?;
+    let decoded = percent_decode_str(&raw)
+        .decode_utf8()
+        .map_err(|e| tide::Error::new(StatusCode::BadRequest, e))?;
+
+    Ok(decoded.into_owned())
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response> {
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(value)?);
+    Ok(response)
+}
+
+// Make a change as whoever the request is logged in as.
+async fn apply(request: &Request<Server>, event: DbEvent) -> Result<Outcome> {
+    request.state().apply(auth::bearer(request), event).await
+}
+
+// Reply with the outcome of a write.
+fn outcome(outcome: Outcome) -> Result<Response> {
+    let mut response = Response::new(outcome.status());
+    response.set_body(Body::from_json(&outcome)?);
+    Ok(response)
+}
+
+async fn list_dinners(request: Request<Server>) -> Result<Response> {
+    let dinners: Vec<DinnerSummary> = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .dinners
+        .iter()
+        .map(|(id, dinner)| DinnerSummary {
+            id: *id,
+            name: dinner.name.clone(),
+            short: dinner.short.clone(),
+            vote: dinner.vote.clone(),
+        })
+        .collect();
+
+    json(&dinners)
+}
+
+async fn get_dinner(request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let db = request.state().database.data.lock().unwrap();
+    let details = db.find_dinner(&index).map(|id| {
+        let dinner = &db.dinners[&id];
+        DinnerDetails {
+            id,
+            name: dinner.name.clone(),
+            short: dinner.short.clone(),
+            long: dinner.long.clone(),
+            vote: dinner.vote.clone(),
+            has_photo: dinner.photo.is_some(),
+            ratings: dinner.rating_summary(),
+        }
+    });
+
+    match details {
+        Some(details) => json(&details),
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+async fn new_dinner(mut request: Request<Server>) -> Result<Response> {
+    let NewDinnerRequest { user, name } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::NewDinner { user, name }).await?)
+}
+
+async fn edit_dinner(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let EditDinnerRequest {
+        user,
+        name,
+        short,
+        long,
+    } = request.body_json().await?;
+    let event = DbEvent::EditDinner {
+        user,
+        index,
+        name,
+        short,
+        long,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn delete_dinner(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    let event = DbEvent::DeleteDinner { user, index };
+    outcome(apply(&request, event).await?)
+}
+
+async fn vote(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::Vote { user, index }).await?)
+}
+
+async fn unvote(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::Unvote { user, index }).await?)
+}
+
+async fn get_photo(request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let photo = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .dinner(&index)
+        .and_then(|dinner| dinner.photo.clone());
+    let Some(photo) = photo else {
+        return Ok(Response::new(StatusCode::NotFound));
+    };
+    let etag = photos::etag(&photo);
+    let cached = request
+        .header("If-None-Match")
+        .is_some_and(|tags| tags.iter().any(|tag| tag.as_str() == etag));
+    let mut response = Response::new(if cached {
+        StatusCode::NotModified
+    } else {
+        StatusCode::Ok
+    });
+
+    // Photos can change, so caches have to check the tag every time
+    response.insert_header("ETag", etag);
+    response.insert_header("Cache-Control", "no-cache");
+    if !cached {
+        let mime = photos::sniff(&photo).unwrap_or("application/octet-stream");
+        response.set_body(photo.to_vec());
+        response.insert_header("Content-Type", mime);
+    }
+
+    Ok(response)
+}
+
+// Upload a photo as the raw request body.
+async fn set_photo(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserQuery { user } = request.query()?;
+    let declared = request.content_type();
+    if declared.is_some_and(|mime| mime.basetype() != "image") {
+        return Ok(Response::new(StatusCode::UnsupportedMediaType));
+    }
+    if request
+        .len()
+        .is_some_and(|len| len > photos::MAX_PHOTO_BYTES)
+    {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+    // Don't trust the length header, stop reading right after the limit
+    let mut photo = Vec::new();
+    request
+        .take_body()
+        .take(photos::MAX_PHOTO_BYTES as u64 + 1)
+        .read_to_end(&mut photo)
+        .await?;
+    if photo.len() > photos::MAX_PHOTO_BYTES {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+    if photos::sniff(&photo).is_none() {
+        return Ok(Response::new(StatusCode::UnsupportedMediaType));
+    }
+
+    let event = DbEvent::EditPhoto {
+        user,
+        index,
+        photo: Some(photo.into()),
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn delete_photo(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    let event = DbEvent::EditPhoto {
+        user,
+        index,
+        photo: None,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn serve(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::Serve { user, index }).await?)
+}
+
+async fn get_dinner_analytics(request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let analytics = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .dinner_analytics(&index, history::now());
+
+    match analytics {
+        Some(analytics) => json(&analytics),
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+async fn set_rating(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let RatingRequest { user, stars, note } = request.body_json().await?;
+    let event = DbEvent::SetRating {
+        user,
+        index,
+        stars,
+        note,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn clear_rating(mut request: Request<Server>) -> Result<Response> {
+    let index = param(&request, "id")?;
+    let UserRequest { user } = request.body_json().await?;
+    let event = DbEvent::ClearRating { user, index };
+    outcome(apply(&request, event).await?)
+}
+
+async fn login(mut request: Request<Server>) -> Result<Response> {
+    let LoginRequest { name, password } = request.body_json().await?;
+    let sessions = &request.state().sessions;
+    if sessions.locked_out(&name) {
+        return outcome(Outcome::rejected(Rejection::LockedOut));
+    }
+    let person = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .people
+        .get(&name)
+        .map(|person| (person.password.clone(), person.deactivated));
+
+    let (hash, deactivated) = person.unwrap_or_default();
+    let correct = match hash {
+        Some(hash) => auth::verify(hash, password).await,
+        None => false,
+    };
+    if !correct {
+        sessions.failed(&name);
+        return outcome(Outcome::rejected(Rejection::WrongPassword));
+    }
+    sessions.succeeded(&name);
+    if deactivated {
+        return outcome(Outcome::rejected(Rejection::Deactivated));
+    }
+    json(&sessions.start(&name))
+}
+
+async fn logout(request: Request<Server>) -> Result<Response> {
+    let logged_in = auth::bearer(&request)
+        .is_some_and(|token| request.state().sessions.end(token));
+    if !logged_in {
+        return outcome(Outcome::rejected(Rejection::NotLoggedIn));
+    }
+    outcome(Outcome::Applied)
+}
+
+// Make `name` the owner, with the token printed when the server started.
+async fn setup(mut request: Request<Server>) -> Result<Response> {
+    let SetupRequest {
+        token,
+        name,
+        secret,
+    } = request.body_json().await?;
+    let password = match optional_hash(secret).await? {
+        Ok(password) => password,
+        Err(rejected) => return outcome(rejected),
+    };
+
+    // Only usable once
+    let state = request.state();
+    let expected = state.setup.lock().unwrap().take();
+    if expected.as_deref() != Some(token.as_str()) {
+        *state.setup.lock().unwrap() = expected;
+        return outcome(Outcome::rejected(Rejection::WrongSetupToken));
+    }
+    let result = state.apply(None, DbEvent::Setup { name, password }).await?;
+    if result.is_applied() {
+        auth::setup_done(&state.config.data_dir);
+    } else {
+        *state.setup.lock().unwrap() = expected;
+    }
+    outcome(result)
+}
+
+async fn new_person(mut request: Request<Server>) -> Result<Response> {
+    let NewPersonRequest {
+        name,
+        invite,
+        secret,
+    } = request.body_json().await?;
+    // Leaving it out is allowed only with logging in turned off
+    let password = match optional_hash(secret).await? {
+        Ok(password) => password,
+        Err(rejected) => return outcome(rejected),
+    };
+    let config = &request.state().config;
+    let event = DbEvent::NewUser {
+        name,
+        votes: config.default_votes,
+        password,
+        invite,
+        invite_needed: !config.legacy_unauthenticated,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn set_password(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let PasswordRequest { user, secret } = request.body_json().await?;
+    if !secret.is_valid() {
+        return outcome(Outcome::rejected(Rejection::InvalidPassword));
+    }
+    let event = DbEvent::SetPassword {
+        user,
+        name,
+        hash: hash(secret).await?,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn rename_person(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let RenamePersonRequest {
+        user,
+        name: new_name,
+    } = request.body_json().await?;
+    let event = DbEvent::RenamePerson {
+        user,
+        name,
+        new_name,
+    };
+    outcome(apply(&request, event).await?)
+}
+
+async fn remove_person(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::RemovePerson { user, name }).await?)
+}
+
+async fn deactivate(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::Deactivate { user, name }).await?)
+}
+
+async fn reactivate(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::Reactivate { user, name }).await?)
+}
+
+async fn set_role(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let RoleRequest { user, role } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::SetRole { user, name, role }).await?)
+}
+
+// Hash a password or PIN if one was given, or say what's wrong with it.
+async fn optional_hash(
+    secret: Secret,
+) -> Result<std::result::Result<Option<String>, Outcome>> {
+    if secret.password.is_none() && secret.pin.is_none() {
+        Ok(Ok(None))
+    } else if secret.is_valid() {
+        Ok(Ok(Some(hash(secret).await?)))
+    } else {
+        Ok(Err(Outcome::rejected(Rejection::InvalidPassword)))
+    }
+}
+
+async fn hash(secret: Secret) -> Result<String> {
+    secret.hash().await.ok_or_else(|| {
+        tide::Error::from_str(
+            StatusCode::InternalServerError,
+            "couldn't hash the password",
+        )
+    })
+}
+
+async fn get_person(request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let details = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .people
+        .get(&name)
+        .map(|person| PersonDetails {
+            name: name.clone(),
+            votes: person.votes,
+            admin: person.role.is_admin(),
+            role: person.role,
+            has_password: person.password.is_some(),
+            deactivated: person.deactivated,
+        });
+
+    match details {
+        Some(details) => json(&details),
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+async fn get_votes(request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let state = request.state();
+    let logged_in = state.check_login(auth::bearer(&request), &name).is_ok();
+    let summary = state
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .vote_summary(&name, logged_in);
+
+    match summary {
+        Some(summary) => json(&summary),
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+async fn set_votes(mut request: Request<Server>) -> Result<Response> {
+    let SetVotesRequest { user, votes } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::SetVotes { user, votes }).await?)
+}
+
+async fn get_analytics(request: Request<Server>) -> Result<Response> {
+    let analytics = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .analytics(history::now());
+
+    json(&analytics)
+}
+
+// Make sure `user` is logged in and may manage the database, for routes that
+// don't go through the database thread.
+fn admin(
+    request: &Request<Server>,
+    user: &str,
+) -> std::result::Result<(), Outcome> {
+    permitted(request, user, Permission::ManageData)
+}
+
+// Make sure `user` is logged in and has `permission`, like `admin()`.
+fn permitted(
+    request: &Request<Server>,
+    user: &str,
+    permission: Permission,
+) -> std::result::Result<(), Outcome> {
+    let state = request.state();
+    state.check_login(auth::bearer(request), user)?;
+    check_permission(&state.database.data.lock().unwrap(), user, permission)
+}
+
+// Invites that can still be used, soonest to expire first.
+async fn list_invites(request: Request<Server>) -> Result<Response> {
+    let UserQuery { user } = request.query()?;
+    if let Err(rejected) = permitted(&request, &user, Permission::ManagePeople)
+    {
+        return outcome(rejected);
+    }
+
+    let now = history::now();
+    let mut invites: Vec<InviteDetails> = request
+        .state()
+        .database
+        .data
+        .lock()
+        .unwrap()
+        .invites
+        .iter()
+        .filter(|(_, invite)| invite.is_valid(now))
+        .map(|(code, invite)| InviteDetails {
+            code: code.clone(),
+            invite: invite.clone(),
+        })
+        .collect();
+    invites.sort_by_key(|details| {
+        (
+            details.invite.expires.unwrap_or(u64::MAX),
+            details.code.clone(),
+        )
+    });
+    json(&invites)
+}
+
+// Make an invite, replying with it.
+async fn new_invite(mut request: Request<Server>) -> Result<Response> {
+    let NewInviteRequest {
+        user,
+        role,
+        votes,
+        uses,
+        expires_in,
+    } = request.body_json().await?;
+    let invite = Invite {
+        role: role.unwrap_or(Role::Member),
+        votes: votes.unwrap_or(request.state().config.default_votes),
+        uses: uses.unwrap_or(1),
+        expires: expires_in
+            .map(|seconds| history::now().saturating_add(seconds)),
+        by: user.clone(),
+    };
+    let code = invites::code();
+    let event = DbEvent::NewInvite {
+        user,
+        code: code.clone(),
+        role: invite.role,
+        votes: invite.votes,
+        uses: invite.uses,
+        expires: invite.expires,
+    };
+    let result = apply(&request, event).await?;
+    if !result.is_applied() {
+        return outcome(result);
+    }
+    let mut response = json(&InviteDetails { code, invite })?;
+    response.set_status(StatusCode::Created);
+    Ok(response)
+}
+
+async fn delete_invite(mut request: Request<Server>) -> Result<Response> {
+    let code = param(&request, "code")?;
+    let UserRequest { user } = request.body_json().await?;
+    outcome(apply(&request, DbEvent::DeleteInvite { user, code }).await?)
+}
+
+async fn list_backups(request: Request<Server>) -> Result<Response> {
+    let UserQuery { user } = request.query()?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+
+    json(&request.state().backups.list()?)
+}
+
+async fn create_backup(mut request: Request<Server>) -> Result<Response> {
+    let UserRequest { user } = request.body_json().await?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+
+    let state = request.state();
+    let data = state.database.data.lock().unwrap().to_serde();
+    let mut response = json(&state.backups.create(&data)?)?;
+    response.set_status(StatusCode::Created);
+    Ok(response)
+}
+
+async fn restore_backup(mut request: Request<Server>) -> Result<Response> {
+    let name = param(&request, "name")?;
+    let UserRequest { user } = request.body_json().await?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+
+    let state = request.state();
+    let Some(data) = state.backups.read(&name)? else {
+        return outcome(Outcome::not_found(Missing::Backup));
+    };
+    let Ok(()) =
+        state
+            .database
+            .replace(&state.backups, &user, "restore", |_| {
+                Ok::<_, Infallible>(data)
+            })?;
+    outcome(Outcome::Applied)
+}
+
+async fn export(request: Request<Server>) -> Result<Response> {
+    let ExportQuery {
+        user,
+        format,
+        photos,
+    } = request.query()?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+
+    let export = request.state().database.data.lock().unwrap().export(photos);
+    let (body, mime, extension) = match format {
+        Format::Json => (export.to_json()?, "application/json", "json"),
+        Format::Csv => (export.to_csv()?, "text/csv", "csv"),
+    };
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(body);
+    response.insert_header("Content-Type", mime);
+    response.insert_header(
+        "Content-Disposition",
+        format!("attachment; filename=\"meal_vote.{extension}\""),
+    );
+    Ok(response)
+}
+
+async fn import(mut request: Request<Server>) -> Result<Response> {
+    let ImportQuery { user, format, mode } = request.query()?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+    let mut file = Vec::new();
+    request
+        .take_body()
+        .take(MAX_IMPORT_BYTES as u64 + 1)
+        .read_to_end(&mut file)
+        .await?;
+    if file.len() > MAX_IMPORT_BYTES {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+
+    let import = match format {
+        Format::Json => Export::from_json(&file),
+        Format::Csv => Export::from_csv(&file),
+    };
+    let state = request.state();
+    let imported = match import {
+        Ok(import) => {
+            state
+                .database
+                .replace(&state.backups, &user, "import", |data| {
+                    data.import(import, mode, &user)
+                })?
+        }
+        Err(e) => Err(vec![e]),
+    };
+
+    match imported {
+        Ok(()) => outcome(Outcome::Applied),
+        Err(errors) => {
+            let mut response = json(&ImportErrors { errors })?;
+            response.set_status(StatusCode::UnprocessableEntity);
+            Ok(response)
+        }
+    }
+}
+
+// Changes the query asks for, oldest first.
+async fn audit_log(request: Request<Server>) -> Result<Response> {
+    let AuditQuery {
+        user,
+        person,
+        dinner,
+        action,
+        from,
+        to,
+    } = request.query()?;
+    if let Err(rejected) = admin(&request, &user) {
+        return outcome(rejected);
+    }
+
+    let filter = Filter {
+        person,
+        dinner,
+        action,
+        from,
+        to,
+    };
+    json(
+        &request
+            .state()
+            .database
+            .audit
+            .lock()
+            .unwrap()
+            .list(&filter)?,
+    )
+}
@@ -0,0 +1,287 @@
+// Audit log of every change: who made it, when, and what it changed.  It's
+// kept in its own file next to the database, so restoring a backup or
+// importing doesn't rewrite it.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{storage::sync_dir, DatabaseData, DbEvent, DinnerId};
+
+// Where the log is kept in the data directory
+const AUDIT_FILE: &str = "audit";
+
+// One change, one line of the log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Entry {
+    // When it was applied, in seconds since the Unix epoch
+    pub(crate) at: u64,
+    // Who made it, the new person for signing up
+    pub(crate) actor: String,
+    // Type of the change, like `delete_dinner`
+    pub(crate) action: String,
+    // What it changed, at most one of these
+    pub(crate) person: Option<String>,
+    pub(crate) dinner: Option<DinnerId>,
+    pub(crate) invite: Option<String>,
+    // What it was like before and after, `null` when it didn't exist
+    pub(crate) before: Value,
+    pub(crate) after: Value,
+}
+
+// Which entries to list, everything that's `None` matches
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    // Made by or changing this person
+    pub(crate) person: Option<String>,
+    pub(crate) dinner: Option<DinnerId>,
+    pub(crate) action: Option<String>,
+    // Applied at or after this
+    pub(crate) from: Option<u64>,
+    // Applied before this
+    pub(crate) to: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.person.as_ref().is_none_or(|person| {
+            entry.actor == *person || entry.person.as_ref() == Some(person)
+        }) && self
+            .dinner
+            .is_none_or(|dinner| entry.dinner == Some(dinner))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| entry.action == *action)
+            && self.from.is_none_or(|from| entry.at >= from)
+            && self.to.is_none_or(|to| entry.at < to)
+    }
+}
+
+// What a change is made to
+#[derive(Clone)]
+enum Target {
+    Person(String),
+    Dinner(DinnerId),
+    // Everyone's votes
+    Votes,
+    Invite(String),
+    // The whole database, for restoring and importing
+    Everything,
+}
+
+impl Target {
+    // What it's like in `db`, without secrets or photos.
+    fn state(&self, db: &DatabaseData) -> Value {
+        match self {
+            Target::Person(name) => match db.people.get(name) {
+                Some(person) => json!({
+                    "votes": person.votes,
+                    "role": person.role,
+                    "has_password": person.password.is_some(),
+                    "deactivated": person.deactivated,
+                }),
+                None => Value::Null,
+            },
+            Target::Dinner(id) => match db.dinners.get(id) {
+                Some(dinner) => json!({
+                    "name": dinner.name,
+                    "short": dinner.short,
+                    "long": dinner.long,
+                    "vote": dinner.vote,
+                    "has_photo": dinner.photo.is_some(),
+                    "ratings": dinner.ratings,
+                }),
+                None => Value::Null,
+            },
+            Target::Votes => json!(db
+                .people
+                .iter()
+                .map(|(name, person)| (name, person.votes))
+                .collect::<BTreeMap<_, _>>()),
+            Target::Invite(code) => match db.invites.get(code) {
+                Some(invite) => json!(invite),
+                None => Value::Null,
+            },
+            Target::Everything => json!({
+                "people": db.people.len(),
+                "dinners": db.dinners.len(),
+                "invites": db.invites.len(),
+            }),
+        }
+    }
+}
+
+// A change about to be made, with what it changes as it was before
+pub(crate) struct Change {
+    actor: String,
+    action: String,
+    before: Option<Target>,
+    // Differs from `before` for renaming
+    after: Option<Target>,
+    state: Value,
+}
+
+impl Change {
+    pub(crate) fn new(db: &DatabaseData, event: &DbEvent) -> Self {
+        let before = match event {
+            DbEvent::NewUser { name, .. }
+            | DbEvent::SetPassword { name, .. }
+            | DbEvent::Setup { name, .. }
+            | DbEvent::SetRole { name, .. }
+            | DbEvent::RenamePerson { name, .. }
+            | DbEvent::RemovePerson { name, .. }
+            | DbEvent::Deactivate { name, .. }
+            | DbEvent::Reactivate { name, .. } => {
+                Some(Target::Person(name.clone()))
+            }
+            DbEvent::NewDinner { .. } => Some(Target::Dinner(db.next_dinner)),
+            DbEvent::Vote { index, .. }
+            | DbEvent::Unvote { index, .. }
+            | DbEvent::EditShortname { index, .. }
+            | DbEvent::EditLongname { index, .. }
+            | DbEvent::EditDetails { index, .. }
+            | DbEvent::EditDinner { index, .. }
+            | DbEvent::EditPhoto { index, .. }
+            | DbEvent::DeleteDinner { index, .. }
+            | DbEvent::SetRating { index, .. }
+            | DbEvent::ClearRating { index, .. }
+            | DbEvent::Serve { index, .. } => {
+                db.find_dinner(index).map(Target::Dinner)
+            }
+            DbEvent::SetVotes { .. } => Some(Target::Votes),
+            DbEvent::NewInvite { code, .. }
+            | DbEvent::DeleteInvite { code, .. } => {
+                Some(Target::Invite(crate::invites::normalize(code)))
+            }
+        };
+        let after = match event {
+            DbEvent::RenamePerson { new_name, .. } => {
+                Some(Target::Person(new_name.clone()))
+            }
+            _ => before.clone(),
+        };
+        let actor = match (event.actor(), &before) {
+            (Some(user), _) => user.to_string(),
+            (None, Some(Target::Person(name))) => name.clone(),
+            (None, _) => String::new(),
+        };
+        Self {
+            actor,
+            action: event.action().to_string(),
+            state: before.as_ref().map_or(Value::Null, |t| t.state(db)),
+            before,
+            after,
+        }
+    }
+
+    // Replacing all of `db`, like `restore`, by `actor`.
+    pub(crate) fn replacing(
+        db: &DatabaseData,
+        actor: &str,
+        action: &str,
+    ) -> Self {
+        Self {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            before: Some(Target::Everything),
+            after: Some(Target::Everything),
+            state: Target::Everything.state(db),
+        }
+    }
+
+    pub(crate) fn action(&self) -> &str {
+        &self.action
+    }
+
+    // The entry for it, once it's been applied to `db` at `at`.
+    pub(crate) fn applied(self, db: &DatabaseData, at: u64) -> Entry {
+        let after = self.after.as_ref().map_or(Value::Null, |t| t.state(db));
+        let (person, dinner, invite) = match self.before {
+            Some(Target::Person(name)) => (Some(name), None, None),
+            Some(Target::Dinner(id)) => (None, Some(id), None),
+            Some(Target::Invite(code)) => (None, None, Some(code)),
+            Some(Target::Votes | Target::Everything) | None => {
+                (None, None, None)
+            }
+        };
+
+        Entry {
+            at,
+            actor: self.actor,
+            action: self.action,
+            person,
+            dinner,
+            invite,
+            before: self.state,
+            after,
+        }
+    }
+}
+
+pub(crate) struct Audit {
+    path: PathBuf,
+    // Opened for appending once something is recorded
+    file: Option<File>,
+}
+
+impl Audit {
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(AUDIT_FILE),
+            file: None,
+        }
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            sync_dir(&self.path)?;
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    pub(crate) fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push('\n');
+
+        let file = self.file()?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+
+    // The entries `filter` matches, oldest first.
+    pub(crate) fn list(&self, filter: &Filter) -> io::Result<Vec<Entry>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            // A line cut short by a crash is skipped
+            let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+                continue;
+            };
+            if filter.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,250 @@
+// Logging in: password and PIN hashes, and the session tokens given out when
+// someone logs in.
+
+use std::{
+    collections::HashMap, io, ops::RangeInclusive, path::Path, sync::Mutex,
+};
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier},
+    Argon2,
+};
+use password_hash::SaltString;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::{history, DbEvent, Server};
+
+// Shortest password, in characters
+const MIN_PASSWORD_CHARS: usize = 8;
+// Number of digits in a PIN, for kids
+const PIN_DIGITS: RangeInclusive<usize> = 4..=8;
+// Failed logins in a row before a name is locked out for a while
+const MAX_FAILURES: u32 = 5;
+// How long that is, in seconds
+const LOCKOUT: u64 = 60;
+// Where the setup token is kept in the data directory, until it's used
+const SETUP_TOKEN_FILE: &str = "setup_token";
+
+// A new password or PIN, only one of them is given
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Secret {
+    #[serde(default)]
+    pub(crate) password: Option<String>,
+    #[serde(default)]
+    pub(crate) pin: Option<String>,
+}
+
+impl Secret {
+    // Whether it's long enough, `false` if neither or both are given.
+    pub(crate) fn is_valid(&self) -> bool {
+        match (&self.password, &self.pin) {
+            (Some(password), None) => {
+                password.chars().count() >= MIN_PASSWORD_CHARS
+            }
+            (None, Some(pin)) => {
+                PIN_DIGITS.contains(&pin.len())
+                    && pin.chars().all(|c| c.is_ascii_digit())
+            }
+            _ => false,
+        }
+    }
+
+    // Hash it for storing.  This is slow on purpose, so it's done off the
+    // request thread.
+    pub(crate) async fn hash(self) -> Option<String> {
+        let secret = self.password.or(self.pin)?;
+        async_std::task::spawn_blocking(move || hash(&secret).ok()).await
+    }
+}
+
+// Hash a password or PIN, as stored with the person.
+pub fn hash(secret: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)?
+        .to_string())
+}
+
+// Check a password or PIN against its hash, off the request thread.
+pub(crate) async fn verify(hash: String, secret: String) -> bool {
+    async_std::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(secret.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+}
+
+// A session, as returned by `POST /api/login`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Login {
+    // Sent back as `Authorization: Bearer {token}`
+    pub(crate) token: String,
+    // When it stops working, in seconds since the Unix epoch
+    pub(crate) expires: u64,
+}
+
+struct Session {
+    person: String,
+    expires: u64,
+}
+
+// Failed logins for a name
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    // When the last one was
+    at: u64,
+}
+
+// Who's logged in, kept in memory so restarting logs everyone out
+pub(crate) struct Sessions {
+    // Seconds a session lasts
+    ttl: u64,
+    // Key is the token
+    sessions: Mutex<HashMap<String, Session>>,
+    // Key is the name tried
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl Sessions {
+    pub(crate) fn new(ttl: u64) -> Self {
+        Self {
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Log `person` in, returning their new session.
+    pub(crate) fn start(&self, person: &str) -> Login {
+        let token = token();
+        let now = history::now();
+        let expires = now.saturating_add(self.ttl);
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                person: person.to_string(),
+                expires,
+            },
+        );
+        Login { token, expires }
+    }
+
+    // Who a token belongs to, `None` if it isn't one or it expired.
+    pub(crate) fn person(&self, token: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(token)?;
+        if session.expires <= history::now() {
+            sessions.remove(token);
+            return None;
+        }
+        Some(session.person.clone())
+    }
+
+    // Log out, returning whether the token was logged in.
+    pub(crate) fn end(&self, token: &str) -> bool {
+        self.sessions.lock().unwrap().remove(token).is_some()
+    }
+
+    // Log `person` out everywhere but `keep`.
+    pub(crate) fn end_all(&self, person: &str, keep: Option<&str>) {
+        self.sessions.lock().unwrap().retain(|token, session| {
+            session.person != person || Some(token.as_str()) == keep
+        });
+    }
+
+    // Keep `person` logged in under their new name.
+    fn rename(&self, person: &str, new_name: &str) {
+        for session in self.sessions.lock().unwrap().values_mut() {
+            if session.person == person {
+                session.person = new_name.to_string();
+            }
+        }
+    }
+
+    // Follow a change to who someone is, once it's applied.  `token` is the
+    // session it was made with.
+    pub(crate) fn update(&self, event: &DbEvent, token: Option<&str>) {
+        match event {
+            // Anyone who knew the old password is logged out
+            DbEvent::SetPassword { name, .. } => self.end_all(name, token),
+            DbEvent::RenamePerson { name, new_name, .. } => {
+                self.rename(name, new_name)
+            }
+            DbEvent::RemovePerson { name, .. }
+            | DbEvent::Deactivate { name, .. } => self.end_all(name, None),
+            _ => {}
+        }
+    }
+
+    // Whether `name` failed to log in too often lately.
+    pub(crate) fn locked_out(&self, name: &str) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|failures| {
+                failures.count >= MAX_FAILURES
+                    && history::now() < failures.at + LOCKOUT
+            })
+    }
+
+    pub(crate) fn failed(&self, name: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        let now = history::now();
+        failures.retain(|_, failures| now < failures.at + LOCKOUT);
+        let failures = failures.entry(name.to_string()).or_default();
+        failures.count += 1;
+        failures.at = now;
+    }
+
+    pub(crate) fn succeeded(&self, name: &str) {
+        self.failures.lock().unwrap().remove(name);
+    }
+}
+
+// A random token that can't be guessed, as hex.
+pub(crate) fn token() -> String {
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Make a setup token, for making someone the owner, and save it in `dir`
+// where only whoever runs the server can read it.
+pub(crate) fn new_setup_token(dir: &Path) -> io::Result<String> {
+    let token = token();
+    let path = dir.join(SETUP_TOKEN_FILE);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(&path)?, token.as_bytes())?;
+    println!(
+        "There's no owner yet, make one with `POST /api/setup` and this \
+            token (also in {}): {token}",
+        path.display(),
+    );
+    Ok(token)
+}
+
+// Forget the setup token once there's an owner.
+pub(crate) fn setup_done(dir: &Path) {
+    let _ = std::fs::remove_file(dir.join(SETUP_TOKEN_FILE));
+}
+
+// The session token a request was sent with.
+pub(crate) fn bearer(request: &tide::Request<Server>) -> Option<&str> {
+    request
+        .header("Authorization")?
+        .last()
+        .as_str()
+        .strip_prefix("Bearer ")
+}
@@ -0,0 +1,128 @@
+// Rotating timestamped backups of the database, which admins can list and
+// restore.
+
+use std::{io, path::PathBuf, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history, migrations,
+    storage::{read_muon, sync_dir, write_temp},
+    Database, DatabaseData, DatabaseDataSerde,
+};
+
+// A backup, as listed by `GET /api/admin/backups`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BackupInfo {
+    // File name, used to restore it
+    pub(crate) name: String,
+    // When it was taken, in seconds since the Unix epoch
+    pub(crate) at: u64,
+    // Size of the file
+    pub(crate) bytes: u64,
+}
+
+pub(crate) struct Backups {
+    dir: PathBuf,
+    // Number of backups kept, the oldest are deleted first
+    keep: usize,
+}
+
+impl Backups {
+    pub(crate) fn new(dir: PathBuf, keep: usize) -> Self {
+        Self { dir, keep }
+    }
+
+    // List the backups, newest first.
+    pub(crate) fn list(&self) -> io::Result<Vec<BackupInfo>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut backups = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Named `{at}.muon`, or `{at}-{n}.muon` if there's more than one
+            // in a second
+            let Some(stem) = name.strip_suffix(".muon") else {
+                continue;
+            };
+            let Ok(at) = stem.split('-').next().unwrap_or(stem).parse() else {
+                continue;
+            };
+            backups.push(BackupInfo {
+                name,
+                at,
+                bytes: entry.metadata()?.len(),
+            });
+        }
+        backups.sort_by(|a, b| b.at.cmp(&a.at).then(b.name.cmp(&a.name)));
+
+        Ok(backups)
+    }
+
+    // Save a backup of `data`, and delete the ones past the number kept.
+    pub(crate) fn create(
+        &self,
+        data: &DatabaseDataSerde,
+    ) -> io::Result<BackupInfo> {
+        std::fs::create_dir_all(&self.dir)?;
+        let at = history::now();
+        let mut name = format!("{at}.muon");
+        for n in 1.. {
+            if !self.dir.join(&name).exists() {
+                break;
+            }
+            name = format!("{at}-{n}.muon");
+        }
+
+        let path = self.dir.join(&name);
+        let temp = write_temp(&path, data)?;
+        std::fs::rename(&temp, &path)?;
+        sync_dir(&path)?;
+        let bytes = std::fs::metadata(&path)?.len();
+
+        for old in self.list()?.iter().skip(self.keep.max(1)) {
+            std::fs::remove_file(self.dir.join(&old.name))?;
+        }
+
+        Ok(BackupInfo { name, at, bytes })
+    }
+
+    // Read a backup, `None` if there's no backup with that name.
+    pub(crate) fn read(&self, name: &str) -> io::Result<Option<DatabaseData>> {
+        // Only names from the list, so nothing outside the directory
+        if !self.list()?.iter().any(|backup| backup.name == name) {
+            return Ok(None);
+        }
+        let Some(mut data) = read_muon(&self.dir.join(name))? else {
+            return Ok(None);
+        };
+        migrations::migrate(&mut data)?;
+
+        Ok(Some(DatabaseData::from_serde(data)))
+    }
+}
+
+// Take a backup every `every`, forever.
+pub(crate) fn schedule(
+    backups: Arc<Backups>,
+    database: Arc<Database>,
+    every: Duration,
+) {
+    loop {
+        std::thread::sleep(every);
+        let data = database.data.lock().unwrap().to_serde();
+        match backups.create(&data) {
+            Ok(backup) => tide::log::info!("Backed up to {}", backup.name),
+            Err(e) => tide::log::error!("Couldn't back up the database: {}", e),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+// Server settings, from (later ones win):
+//  1. The defaults
+//  2. A TOML file, `meal_vote.toml` in the working directory if there is one
+//  3. Environment variables, `MEAL_VOTE_` and the setting in capitals
+//  4. Command line flags, `--` and the setting with `-` for `_`
+
+use std::{
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+use tide::log::LevelFilter;
+
+use crate::storage::Backend;
+
+// File read if no other is given
+const DEFAULT_FILE: &str = "meal_vote.toml";
+
+// Settings that can be given as environment variables or flags, and what they
+// are for `--help`
+const SETTINGS: [(&str, &str); 11] = [
+    ("listen", "addresses to listen on, separated by commas"),
+    (
+        "data_dir",
+        "directory the database, backups and audit log are kept in",
+    ),
+    (
+        "storage",
+        "storage backend: journal, muon, sqlite or memory",
+    ),
+    ("default_votes", "number of votes new people start with"),
+    ("backup_every", "seconds between backups, 0 for none"),
+    ("backup_keep", "number of backups kept"),
+    (
+        "notify_every",
+        "seconds between notifications, 0 for only one",
+    ),
+    ("notify_message", "what notifications say"),
+    ("log", "log level: off, error, warn, info, debug or trace"),
+    ("session_ttl", "seconds a login lasts"),
+    (
+        "legacy_unauthenticated",
+        "true to let anyone act as anyone, for old apps",
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Addresses to listen on
+    pub listen: Vec<String>,
+    // Where the database, backups and audit log are kept
+    pub data_dir: PathBuf,
+    pub storage: Backend,
+    // Number of votes new people start with
+    pub default_votes: u16,
+    // Seconds between backups, 0 for none
+    pub backup_every: u64,
+    // Number of backups kept
+    pub backup_keep: usize,
+    // Seconds between notifications to each listener, 0 to only send one when
+    // they connect
+    pub notify_every: u64,
+    pub notify_message: String,
+    pub log: LevelFilter,
+    // Seconds a login lasts
+    pub session_ttl: u64,
+    // Skip logging in, so anyone can act as anyone like before accounts
+    pub legacy_unauthenticated: bool,
+    // Print what migrating the database would change, then stop
+    pub migrate_dry_run: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec!["0.0.0.0:8080".to_string()],
+            data_dir: PathBuf::from("."),
+            storage: Backend::Journal,
+            default_votes: 0,
+            backup_every: 24 * 60 * 60,
+            backup_keep: 14,
+            notify_every: 0,
+            notify_message: "Time to vote!".to_string(),
+            log: LevelFilter::Info,
+            session_ttl: 30 * 24 * 60 * 60,
+            legacy_unauthenticated: false,
+            migrate_dry_run: false,
+        }
+    }
+}
+
+// The TOML file, every setting is optional
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct File {
+    listen: Option<Vec<String>>,
+    data_dir: Option<PathBuf>,
+    storage: Option<String>,
+    default_votes: Option<u16>,
+    log: Option<String>,
+    session_ttl: Option<u64>,
+    legacy_unauthenticated: Option<bool>,
+    #[serde(default)]
+    backups: FileBackups,
+    #[serde(default)]
+    notify: FileNotify,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileBackups {
+    every: Option<u64>,
+    keep: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileNotify {
+    every: Option<u64>,
+    message: Option<String>,
+}
+
+impl Config {
+    // Read the settings, `args` being the command line flags (without the
+    // program name).  `None` if `--help` was asked for.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<Option<Self>, String> {
+        let flags = Flags::parse(args)?;
+        if flags.help {
+            return Ok(None);
+        }
+
+        let mut config = Config::default();
+        let file = flags
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("MEAL_VOTE_CONFIG").map(Into::into));
+        match file {
+            Some(path) => config.read_file(&path)?,
+            None if Path::new(DEFAULT_FILE).exists() => {
+                config.read_file(Path::new(DEFAULT_FILE))?
+            }
+            None => {}
+        }
+        for (name, _) in SETTINGS {
+            let var = format!("MEAL_VOTE_{}", name.to_uppercase());
+            match std::env::var(&var) {
+                Ok(value) => config
+                    .set(name, &value)
+                    .map_err(|e| format!("{var}: {e}"))?,
+                Err(std::env::VarError::NotPresent) => {}
+                Err(e) => return Err(format!("{var}: {e}")),
+            }
+        }
+        for (name, value) in &flags.settings {
+            config
+                .set(name, value)
+                .map_err(|e| format!("--{}: {e}", name.replace('_', "-")))?;
+        }
+        config.migrate_dry_run = flags.migrate_dry_run;
+
+        config.check()?;
+        Ok(Some(config))
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<(), String> {
+        let error =
+            |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        let text = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+        let file: File = toml::from_str(&text).map_err(|e| error(&e))?;
+
+        if let Some(listen) = file.listen {
+            self.listen = listen;
+        }
+        if let Some(data_dir) = file.data_dir {
+            // Relative to the file
+            self.data_dir =
+                path.parent().unwrap_or(Path::new(".")).join(data_dir);
+        }
+        if let Some(storage) = file.storage {
+            self.storage = storage.parse().map_err(|e| error(&e))?;
+        }
+        if let Some(default_votes) = file.default_votes {
+            self.default_votes = default_votes;
+        }
+        if let Some(log) = file.log {
+            self.log = parse_log(&log).map_err(|e| error(&e))?;
+        }
+        if let Some(session_ttl) = file.session_ttl {
+            self.session_ttl = session_ttl;
+        }
+        if let Some(legacy) = file.legacy_unauthenticated {
+            self.legacy_unauthenticated = legacy;
+        }
+        if let Some(every) = file.backups.every {
+            self.backup_every = every;
+        }
+        if let Some(keep) = file.backups.keep {
+            self.backup_keep = keep;
+        }
+        if let Some(every) = file.notify.every {
+            self.notify_every = every;
+        }
+        if let Some(message) = file.notify.message {
+            self.notify_message = message;
+        }
+        Ok(())
+    }
+
+    // Set one of `SETTINGS` from text.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "listen" => {
+                self.listen = value
+                    .split(',')
+                    .map(|address| address.trim().to_string())
+                    .collect()
+            }
+            "data_dir" => self.data_dir = value.into(),
+            "storage" => self.storage = value.parse()?,
+            "default_votes" => self.default_votes = number(value)?,
+            "backup_every" => self.backup_every = number(value)?,
+            "backup_keep" => self.backup_keep = number(value)?,
+            "notify_every" => self.notify_every = number(value)?,
+            "notify_message" => self.notify_message = value.to_string(),
+            "log" => self.log = parse_log(value)?,
+            "session_ttl" => self.session_ttl = number(value)?,
+            "legacy_unauthenticated" => {
+                self.legacy_unauthenticated = boolean(value)?
+            }
+            _ => return Err(format!("unknown setting `{name}`")),
+        }
+        Ok(())
+    }
+
+    // Reject settings the server can't run with.
+    fn check(&self) -> Result<(), String> {
+        if self.listen.is_empty() {
+            return Err("listen: there must be at least one address".into());
+        }
+        for address in &self.listen {
+            match address.to_socket_addrs().map(|mut addrs| addrs.next()) {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    return Err(format!("listen: `{address}` has no addresses"))
+                }
+                Err(e) => {
+                    return Err(format!("listen: `{address}` isn't valid: {e}"))
+                }
+            }
+        }
+        if !self.data_dir.is_dir() {
+            return Err(format!(
+                "data_dir: `{}` isn't a directory",
+                self.data_dir.display(),
+            ));
+        }
+        if self.backup_keep == 0 {
+            return Err("backup_keep: must keep at least one backup".into());
+        }
+        if self.notify_message.is_empty() {
+            return Err("notify_message: can't be empty".into());
+        }
+        if self.session_ttl == 0 {
+            return Err(
+                "session_ttl: logins must last at least a second".into()
+            );
+        }
+        Ok(())
+    }
+}
+
+// Usage, for `--help`.
+pub fn help() -> String {
+    let mut flags = vec![
+        (
+            "--config PATH".to_string(),
+            "settings file (default meal_vote.toml)",
+        ),
+        (
+            "--migrate-dry-run".to_string(),
+            "print what migrating the database would change",
+        ),
+        ("--help".to_string(), "print this"),
+    ];
+    for (name, about) in SETTINGS {
+        flags.push((format!("--{} VALUE", name.replace('_', "-")), about));
+    }
+
+    let mut help = "Usage: tide-server [FLAGS]\n\n".to_string();
+    for (flag, about) in flags {
+        help.push_str(&format!("{flag:<24} {about}\n"));
+    }
+    help.push_str(
+        "\nEvery setting can also be set with a `MEAL_VOTE_` environment \
+            variable,\nlike MEAL_VOTE_DATA_DIR, or in the settings file.\n",
+    );
+    help
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{value}` isn't a number in range"))
+}
+
+fn boolean(value: &str) -> Result<bool, String> {
+    match value.trim() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("`{value}` isn't true or false")),
+    }
+}
+
+fn parse_log(value: &str) -> Result<LevelFilter, String> {
+    value
+        .parse()
+        .map_err(|_| format!("unknown log level `{value}`"))
+}
+
+// Command line flags
+#[derive(Default)]
+struct Flags {
+    config: Option<PathBuf>,
+    migrate_dry_run: bool,
+    help: bool,
+    // Settings in the order given, as `(name, value)`
+    settings: Vec<(&'static str, String)>,
+}
+
+impl Flags {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut flags = Flags::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("unexpected argument `{arg}`"));
+            };
+            // Either `--flag value` or `--flag=value`
+            let (flag, inline) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("--{flag} needs a value"))
+            };
+
+            match flag {
+                "help" => flags.help = true,
+                "migrate-dry-run" => flags.migrate_dry_run = true,
+                "config" => flags.config = Some(value()?.into()),
+                _ => {
+                    let name = flag.replace('-', "_");
+                    let Some((name, _)) =
+                        SETTINGS.iter().find(|(setting, _)| *setting == name)
+                    else {
+                        return Err(format!("unknown flag `--{flag}`"));
+                    };
+                    flags.settings.push((name, value()?));
+                }
+            }
+        }
+
+        Ok(flags)
+    }
+}
@@ -0,0 +1,84 @@
+// Record of votes and served dinners, kept for analytics.
+
+use std::{
+    convert::TryFrom,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::DinnerId;
+
+// Something that happened to a dinner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEvent {
+    // Whose vote it was
+    pub person: String,
+    pub dinner: DinnerId,
+    pub kind: HistoryKind,
+    // Seconds since the Unix epoch
+    pub at: u64,
+}
+
+// A `HistoryEvent` as stored, where version 1 files have the dinner's name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredEvent {
+    pub(crate) person: String,
+    pub(crate) dinner: String,
+    pub(crate) kind: HistoryKind,
+    pub(crate) at: u64,
+}
+
+impl From<HistoryEvent> for StoredEvent {
+    fn from(event: HistoryEvent) -> Self {
+        Self {
+            person: event.person,
+            dinner: event.dinner.to_string(),
+            kind: event.kind,
+            at: event.at,
+        }
+    }
+}
+
+// What happened (stored as a string, since muon doesn't do enums)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
+pub enum HistoryKind {
+    // Someone voted for the dinner
+    Vote,
+    // Someone's vote was taken back
+    Unvote,
+    // The vote won, and the dinner was served
+    Serve,
+}
+
+impl From<HistoryKind> for String {
+    fn from(kind: HistoryKind) -> Self {
+        match kind {
+            HistoryKind::Vote => "vote",
+            HistoryKind::Unvote => "unvote",
+            HistoryKind::Serve => "serve",
+        }
+        .to_string()
+    }
+}
+
+impl TryFrom<String> for HistoryKind {
+    type Error = String;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        match kind.as_str() {
+            "vote" => Ok(HistoryKind::Vote),
+            "unvote" => Ok(HistoryKind::Unvote),
+            "serve" => Ok(HistoryKind::Serve),
+            _ => Err(format!("unknown history event `{kind}`")),
+        }
+    }
+}
+
+// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
@@ -0,0 +1,55 @@
+// Invite codes, which new accounts need once there's an owner.  Admins make
+// them, and each one gives the accounts made with it a role and votes.
+
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::roles::Role;
+
+// Letters codes are made of, leaving out ones easily mixed up (0 and O, 1
+// and I)
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+// Groups of 4 letters in a code
+const CODE_GROUPS: usize = 3;
+
+// An invite, the code is its key in `DatabaseData::invites`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    // Role of the accounts made with it
+    pub role: Role,
+    // Votes they start with
+    pub votes: u16,
+    // Accounts that can still be made with it
+    pub uses: u32,
+    // When it stops working, in seconds since the Unix epoch
+    #[serde(default)]
+    pub expires: Option<u64>,
+    // Who made it
+    pub by: String,
+}
+
+impl Invite {
+    // Whether it can still be used at `at`.
+    pub fn is_valid(&self, at: u64) -> bool {
+        self.uses > 0 && self.expires.is_none_or(|expires| at < expires)
+    }
+}
+
+// A new code, like `ABCD-EFGH-JKLM`.
+pub(crate) fn code() -> String {
+    let groups: Vec<String> = (0..CODE_GROUPS)
+        .map(|_| {
+            (0..4)
+                .map(|_| {
+                    CODE_LETTERS[OsRng.gen_range(0..CODE_LETTERS.len())] as char
+                })
+                .collect()
+        })
+        .collect();
+    groups.join("-")
+}
+
+// A code as typed in, which may be in lower case or have spaces around it.
+pub(crate) fn normalize(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
@@ -0,0 +1,216 @@
+// Storage as a journal of changes, so a change only appends one line.  Now
+// and then the journal is compacted into a muon snapshot.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apply_event, history,
+    storage::{sync_dir, MuonFile, Storage},
+    DatabaseData, DbEvent,
+};
+
+// Number of changes between snapshots
+const COMPACT_EVERY: u64 = 1000;
+
+// One line of the journal
+#[derive(Serialize, Deserialize)]
+struct Entry<E> {
+    // Counts up from 1, never reset
+    seq: u64,
+    // When the change was applied, in seconds since the Unix epoch
+    at: u64,
+    event: E,
+}
+
+pub(crate) struct Journal {
+    snapshot: MuonFile,
+    path: PathBuf,
+    // Opened for appending once loaded
+    file: Option<File>,
+    // Last entry written
+    seq: u64,
+    // Entries since the last snapshot
+    pending: u64,
+}
+
+impl Journal {
+    pub(crate) fn new(snapshot: PathBuf, path: PathBuf) -> Self {
+        Self {
+            snapshot: MuonFile::new(snapshot),
+            path,
+            file: None,
+            seq: 0,
+            pending: 0,
+        }
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            sync_dir(&self.path)?;
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    // Apply the journal entries that aren't in the snapshot yet, returning
+    // whether there were any.
+    fn replay(&mut self, data: &mut DatabaseData) -> io::Result<bool> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        // Length of the entries read so far
+        let mut good = 0;
+        let mut any = false;
+
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            // A crash can leave the last line half written, drop it
+            if !line.ends_with('\n') {
+                tide::log::warn!("Dropping unfinished journal entry");
+                self.truncate(good)?;
+                break;
+            }
+            let entry: Entry<DbEvent> = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.quarantine(good, e)?;
+                    break;
+                }
+            };
+            good += read as u64;
+
+            if entry.seq > self.seq + 1 {
+                tide::log::error!(
+                    "Journal entries {} to {} are missing, those changes \
+                        are lost",
+                    self.seq + 1,
+                    entry.seq - 1,
+                );
+            }
+            // Already in the snapshot, if compacting was cut short
+            if entry.seq <= self.seq {
+                continue;
+            }
+            apply_event(data, entry.event, entry.at);
+            self.seq = entry.seq;
+            self.pending += 1;
+            any = true;
+        }
+
+        Ok(any)
+    }
+
+    // Keep a copy of a corrupt journal, and drop everything from the corrupt
+    // entry on, which starts at byte `good`.
+    fn quarantine(
+        &self,
+        good: u64,
+        error: serde_json::Error,
+    ) -> io::Result<()> {
+        let quarantine = self
+            .path
+            .with_extension(format!("corrupt-{}", history::now()));
+        std::fs::copy(&self.path, &quarantine)?;
+        tide::log::error!(
+            "{} is corrupt after entry {} ({}), copied it to {}, the \
+                changes after that are lost",
+            self.path.display(),
+            self.seq,
+            error,
+            quarantine.display(),
+        );
+        self.truncate(good)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        file.set_len(len)?;
+        file.sync_all()
+    }
+}
+
+impl Storage for Journal {
+    fn load(&mut self) -> io::Result<Option<DatabaseData>> {
+        let snapshot = self.snapshot.read()?;
+        self.seq = snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.journal)
+            .unwrap_or(0);
+        let found = snapshot.is_some();
+        let mut data =
+            snapshot.map(DatabaseData::from_serde).unwrap_or_default();
+
+        let replayed = self.replay(&mut data)?;
+        Ok((found || replayed).then_some(data))
+    }
+
+    fn apply(
+        &mut self,
+        event: &DbEvent,
+        at: u64,
+        data: &DatabaseData,
+    ) -> io::Result<()> {
+        let entry = Entry {
+            seq: self.seq + 1,
+            at,
+            event,
+        };
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push('\n');
+
+        let file = self.file()?;
+        let len = file.metadata()?.len();
+        if let Err(e) = file
+            .write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+        {
+            // Don't leave half an entry for the next one to be appended to
+            let _ = file.set_len(len);
+            return Err(e);
+        }
+        self.seq += 1;
+        self.pending += 1;
+
+        // The change is saved either way, compacting is tried again next time
+        if self.pending >= COMPACT_EVERY {
+            if let Err(e) = self.snapshot(data) {
+                tide::log::error!("Couldn't compact the journal: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&mut self, data: &DatabaseData) -> io::Result<()> {
+        let mut snapshot = data.to_serde();
+        snapshot.journal = Some(self.seq);
+        self.snapshot.write(&snapshot)?;
+
+        // Entries up to `seq` are skipped on replay, so it's fine if this
+        // doesn't happen
+        let file = self.file()?;
+        file.set_len(0)?;
+        file.sync_all()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,1433 @@
+// The MealVote server: the dinners, people and votes, how they're stored, the
+// protocol the app speaks and the HTTP routes.
+
+mod analytics;
+mod api;
+mod audit;
+pub mod auth;
+mod backups;
+pub mod config;
+pub mod history;
+pub mod invites;
+mod journal;
+pub mod migrations;
+pub mod outcome;
+mod photos;
+pub mod protocol;
+pub mod ratings;
+pub mod roles;
+mod sqlite;
+pub mod storage;
+mod transfer;
+mod votes;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use audit::{Audit, Change};
+use auth::Sessions;
+use backups::Backups;
+use config::Config;
+use history::{HistoryEvent, HistoryKind, StoredEvent};
+use invites::Invite;
+use outcome::{Missing, Outcome, Rejection};
+use protocol::Command;
+use ratings::Rating;
+use roles::{Permission, Role};
+use serde::{Deserialize, Serialize};
+use storage::Storage;
+use tide::{sse, Result};
+
+// Generated ID of a dinner option, never changes
+pub type DinnerId = u64;
+
+// A dinner option
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Dinner {
+    // Short description
+    pub short: String,
+    // Long description
+    pub long: String,
+    // Photo of the dinner option, shared so copying the database doesn't
+    // copy it
+    pub photo: Option<Arc<[u8]>>,
+    // Who voted for this one, if anyone
+    pub vote: Option<String>,
+    // What people thought of it
+    #[serde(default)]
+    pub ratings: Vec<Rating>,
+    // Dinner name (stored in `DinnerKV`)
+    #[serde(skip)]
+    pub name: String,
+}
+
+// A person
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Person {
+    // Number of votes.
+    pub votes: u16,
+    // What they're allowed to do
+    pub role: Role,
+    // Hash of their password or PIN, `None` until they set one
+    pub password: Option<String>,
+    // Can't log in or change anything until an admin reactivates them
+    pub deactivated: bool,
+}
+
+// Database of dinners & votes
+#[derive(Clone, Default)]
+pub struct DatabaseData {
+    // Key is dinner ID
+    pub dinners: HashMap<DinnerId, Dinner>,
+    // ID for the next new dinner
+    pub next_dinner: DinnerId,
+    // Key is person name
+    pub people: HashMap<String, Person>,
+    // Votes and served dinners, oldest first
+    pub history: Vec<HistoryEvent>,
+    // Key is the code
+    pub invites: HashMap<String, Invite>,
+}
+
+impl DatabaseData {
+    // Convert from the stored form, which must be migrated to the current
+    // version.
+    fn from_serde(database_data: DatabaseDataSerde) -> Self {
+        let mut dinners = HashMap::new();
+        let mut people = HashMap::new();
+
+        for DinnerKV { key, value, name } in database_data.dinners {
+            let (Ok(id), Some(name)) = (key.parse(), name) else {
+                tide::log::warn!("Skipping dinner `{}`, it has no ID", key);
+                continue;
+            };
+            dinners.insert(id, Dinner { name, ..value });
+        }
+
+        for PersonKV { key, value } in database_data.people {
+            let role = value.role.unwrap_or(Role::from_admin(value.admin));
+            people.insert(
+                key,
+                Person {
+                    votes: value.votes,
+                    role,
+                    password: value.password,
+                    deactivated: value.deactivated.unwrap_or(false),
+                },
+            );
+        }
+
+        let history = database_data
+            .history
+            .into_iter()
+            .filter_map(|event| {
+                Some(HistoryEvent {
+                    person: event.person,
+                    dinner: event.dinner.parse().ok()?,
+                    kind: event.kind,
+                    at: event.at,
+                })
+            })
+            .collect();
+
+        let invites = database_data
+            .invites
+            .into_iter()
+            .map(|InviteKV { key, value }| (key, value))
+            .collect();
+
+        Self {
+            dinners,
+            next_dinner: database_data.next_dinner.unwrap_or(0),
+            people,
+            history,
+            invites,
+        }
+    }
+
+    fn to_serde(&self) -> DatabaseDataSerde {
+        let mut dinners = Vec::new();
+        let mut people = Vec::new();
+
+        for (key, value) in self.dinners.clone() {
+            dinners.push(DinnerKV {
+                key: key.to_string(),
+                name: Some(value.name.clone()),
+                value,
+            });
+        }
+
+        for (key, person) in self.people.clone() {
+            let value = StoredPerson {
+                votes: person.votes,
+                admin: person.role.is_admin(),
+                password: person.password,
+                role: Some(person.role),
+                deactivated: person.deactivated.then_some(true),
+            };
+            people.push(PersonKV { key, value });
+        }
+
+        DatabaseDataSerde {
+            dinners,
+            next_dinner: Some(self.next_dinner),
+            people,
+            history: self.history.iter().cloned().map(Into::into).collect(),
+            invites: self
+                .invites
+                .iter()
+                .map(|(key, value)| InviteKV {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            journal: None,
+            version: Some(migrations::VERSION),
+        }
+    }
+
+    // Find a dinner by ID, or by name for clients from before IDs.
+    pub fn find_dinner(&self, index: &str) -> Option<DinnerId> {
+        dinner_id(&self.dinners, index)
+    }
+
+    pub fn dinner(&self, index: &str) -> Option<&Dinner> {
+        self.dinners.get(&self.find_dinner(index)?)
+    }
+
+    // Give `name` a new name everywhere it's used.
+    fn rename_person(&mut self, name: &str, new_name: &str) {
+        let Some(person) = self.people.remove(name) else {
+            return;
+        };
+        self.people.insert(new_name.to_string(), person);
+        for dinner in self.dinners.values_mut() {
+            if dinner.vote.as_deref() == Some(name) {
+                dinner.vote = Some(new_name.to_string());
+            }
+            for rating in &mut dinner.ratings {
+                if rating.person == name {
+                    rating.person = new_name.to_string();
+                }
+            }
+        }
+        for event in &mut self.history {
+            if event.person == name {
+                event.person = new_name.to_string();
+            }
+        }
+        for invite in self.invites.values_mut() {
+            if invite.by == name {
+                invite.by = new_name.to_string();
+            }
+        }
+    }
+
+    // Take back `name`'s votes, giving them back what they spent.
+    fn release_votes(&mut self, name: &str) {
+        for dinner in self.dinners.values_mut() {
+            if dinner.vote.as_deref() != Some(name) {
+                continue;
+            }
+            dinner.vote = None;
+            if let Some(person) = self.people.get_mut(name) {
+                if !person.role.can(Permission::VoteForFree) {
+                    person.votes = person.votes.saturating_add(1);
+                }
+            }
+        }
+    }
+
+    // Remove `name`, along with their votes and ratings.  The history of
+    // what they voted for is kept.
+    fn remove_person(&mut self, name: &str) {
+        self.release_votes(name);
+        self.people.remove(name);
+        for dinner in self.dinners.values_mut() {
+            dinner.ratings.retain(|rating| rating.person != name);
+        }
+    }
+}
+
+fn dinner_id(
+    dinners: &HashMap<DinnerId, Dinner>,
+    index: &str,
+) -> Option<DinnerId> {
+    match index.parse() {
+        Ok(id) if dinners.contains_key(&id) => Some(id),
+        _ => dinner_named(dinners, index),
+    }
+}
+
+fn dinner_named(
+    dinners: &HashMap<DinnerId, Dinner>,
+    name: &str,
+) -> Option<DinnerId> {
+    dinners
+        .iter()
+        .find(|(_, dinner)| dinner.name == name)
+        .map(|(id, _)| *id)
+}
+
+// Like `DatabaseData::find_dinner()`, borrowing only the dinners.
+fn dinner_mut<'a>(
+    dinners: &'a mut HashMap<DinnerId, Dinner>,
+    index: &str,
+) -> Option<(DinnerId, &'a mut Dinner)> {
+    let id = dinner_id(dinners, index)?;
+    Some((id, dinners.get_mut(&id)?))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DinnerKV {
+    // Dinner ID (name in version 1)
+    key: String,
+    value: Dinner,
+    // Dinner name (missing in version 1)
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct InviteKV {
+    // The code
+    key: String,
+    value: Invite,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PersonKV {
+    key: String,
+    value: StoredPerson,
+}
+
+// A `Person` as stored, where version 2 files only say if they're an admin
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredPerson {
+    votes: u16,
+    // Still written, for older servers
+    admin: bool,
+    #[serde(default)]
+    password: Option<String>,
+    // Missing in version 2
+    #[serde(default)]
+    role: Option<Role>,
+    // Missing before deactivating, and only written when they are (muon
+    // can only leave out an `Option`)
+    #[serde(default)]
+    deactivated: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DatabaseDataSerde {
+    dinners: Vec<DinnerKV>,
+    people: Vec<PersonKV>,
+    #[serde(default)]
+    history: Vec<StoredEvent>,
+    // Missing before invites
+    #[serde(default)]
+    invites: Vec<InviteKV>,
+    // Missing in version 1
+    #[serde(default)]
+    next_dinner: Option<DinnerId>,
+    // Last journal entry included, for the journal backend
+    #[serde(default)]
+    journal: Option<u64>,
+    // Layout of the file, see `migrations`
+    #[serde(default)]
+    version: Option<u32>,
+}
+
+// A "database"
+pub struct Database {
+    data: std::sync::Mutex<DatabaseData>,
+    storage: std::sync::Mutex<Box<dyn Storage>>,
+    audit: std::sync::Mutex<Audit>,
+}
+
+impl Database {
+    // Open the database from `storage`, keeping its audit log in `dir`.
+    pub fn open(
+        mut storage: Box<dyn Storage>,
+        dir: &std::path::Path,
+    ) -> std::io::Result<Self> {
+        let data = storage.load()?.unwrap_or_default();
+
+        Ok(Database {
+            data: std::sync::Mutex::new(data),
+            storage: std::sync::Mutex::new(storage),
+            audit: std::sync::Mutex::new(Audit::new(dir)),
+        })
+    }
+
+    // Replace everything with what `change` makes of it as one change by
+    // `user`, after backing up what's replaced.  `action` is what the audit log
+    // calls it.  Nothing changes if `change` fails.
+    fn replace<E>(
+        &self,
+        backups: &Backups,
+        user: &str,
+        action: &str,
+        change: impl FnOnce(&DatabaseData) -> std::result::Result<DatabaseData, E>,
+    ) -> std::io::Result<std::result::Result<(), E>> {
+        let mut current = self.data.lock().unwrap();
+        let data = match change(&current) {
+            Ok(data) => data,
+            Err(e) => return Ok(Err(e)),
+        };
+        let audited = Change::replacing(&current, user, action);
+        backups.create(&current.to_serde())?;
+        self.storage.lock().unwrap().snapshot(&data)?;
+        *current = data;
+        self.audit(audited.applied(&current, history::now()));
+        Ok(Ok(()))
+    }
+
+    // Add to the audit log.  The change is already saved, so failing to
+    // record it is only reported.
+    fn audit(&self, entry: audit::Entry) {
+        if let Err(e) = self.audit.lock().unwrap().record(&entry) {
+            tide::log::error!("Couldn't write the audit log: {}", e);
+        }
+    }
+
+    // Apply an event, saving it only if it was applied.  It's applied to a
+    // copy, so nothing changes if it can't be saved.  The copy shares the
+    // photos, which are most of the database.
+    pub fn update(&self, event: DbEvent) -> Outcome {
+        let mut data = self.data.lock().unwrap();
+        let at = history::now();
+        let change = Change::new(&data, &event);
+        let mut changed = data.clone();
+        let outcome = apply_event(&mut changed, event.clone(), at);
+        // Not the event, which can have password hashes and photos
+        tide::log::debug!("{}: {}", change.action(), outcome);
+        if !outcome.is_applied() {
+            return outcome;
+        }
+        if let Err(e) = self.storage.lock().unwrap().apply(&event, at, &changed)
+        {
+            tide::log::error!("Couldn't save a change: {}", e);
+            return Outcome::NotSaved;
+        }
+        *data = changed;
+        self.audit(change.applied(&data, at));
+        outcome
+    }
+}
+
+// A change to the database (also how it's written to the journal)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DbEvent {
+    NewUser {
+        name: String,
+        // Votes they start with
+        #[serde(default)]
+        votes: u16,
+        // Hash of their password or PIN
+        #[serde(default)]
+        password: Option<String>,
+        // Invite code, which sets their role and votes instead
+        #[serde(default)]
+        invite: Option<String>,
+        // Whether they need an invite, unless they're the first one
+        #[serde(default)]
+        invite_needed: bool,
+    },
+    Vote {
+        user: String,
+        index: String,
+    },
+    Unvote {
+        user: String,
+        index: String,
+    },
+    NewDinner {
+        user: String,
+        name: String,
+    },
+    EditShortname {
+        user: String,
+        index: String,
+        name: String,
+    },
+    EditLongname {
+        user: String,
+        index: String,
+        name: String,
+    },
+    EditDetails {
+        user: String,
+        index: String,
+        name: String,
+    },
+    // Change any of the name and descriptions, all or none of them
+    EditDinner {
+        user: String,
+        index: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        short: Option<String>,
+        #[serde(default)]
+        long: Option<String>,
+    },
+    // Set the photo, or remove it if `None`
+    EditPhoto {
+        user: String,
+        index: String,
+        #[serde(with = "photos::base64")]
+        photo: Option<Arc<[u8]>>,
+    },
+    DeleteDinner {
+        user: String,
+        index: String,
+    },
+    SetRating {
+        user: String,
+        index: String,
+        stars: u8,
+        note: Option<String>,
+    },
+    ClearRating {
+        user: String,
+        index: String,
+    },
+    Serve {
+        user: String,
+        index: String,
+    },
+    SetVotes {
+        user: String,
+        votes: u16,
+    },
+    // Set `name`'s password or PIN, `hash` being its hash
+    SetPassword {
+        user: String,
+        name: String,
+        hash: String,
+    },
+    // Make `name` the owner when there isn't one, with the setup token
+    Setup {
+        name: String,
+        // Hash of a password or PIN for them
+        #[serde(default)]
+        password: Option<String>,
+    },
+    // Change `name`'s role, making someone the owner hands ownership over
+    SetRole {
+        user: String,
+        name: String,
+        role: Role,
+    },
+    RenamePerson {
+        user: String,
+        name: String,
+        new_name: String,
+    },
+    RemovePerson {
+        user: String,
+        name: String,
+    },
+    // Stop `name` logging in and making changes, taking back their votes
+    Deactivate {
+        user: String,
+        name: String,
+    },
+    Reactivate {
+        user: String,
+        name: String,
+    },
+    // Make an invite, usable `uses` times until `expires`
+    NewInvite {
+        user: String,
+        code: String,
+        role: Role,
+        votes: u16,
+        uses: u32,
+        expires: Option<u64>,
+    },
+    DeleteInvite {
+        user: String,
+        code: String,
+    },
+}
+
+impl DbEvent {
+    // Who's making the change, `None` for signing up.
+    pub fn actor(&self) -> Option<&str> {
+        match self {
+            DbEvent::NewUser { .. } | DbEvent::Setup { .. } => None,
+            DbEvent::Vote { user, .. }
+            | DbEvent::Unvote { user, .. }
+            | DbEvent::NewDinner { user, .. }
+            | DbEvent::EditShortname { user, .. }
+            | DbEvent::EditLongname { user, .. }
+            | DbEvent::EditDetails { user, .. }
+            | DbEvent::EditDinner { user, .. }
+            | DbEvent::EditPhoto { user, .. }
+            | DbEvent::DeleteDinner { user, .. }
+            | DbEvent::SetRating { user, .. }
+            | DbEvent::ClearRating { user, .. }
+            | DbEvent::Serve { user, .. }
+            | DbEvent::SetVotes { user, .. }
+            | DbEvent::SetPassword { user, .. }
+            | DbEvent::SetRole { user, .. }
+            | DbEvent::RenamePerson { user, .. }
+            | DbEvent::RemovePerson { user, .. }
+            | DbEvent::Deactivate { user, .. }
+            | DbEvent::Reactivate { user, .. }
+            | DbEvent::NewInvite { user, .. }
+            | DbEvent::DeleteInvite { user, .. } => Some(user),
+        }
+    }
+
+    // The journal's name for it.
+    pub fn action(&self) -> &'static str {
+        match self {
+            DbEvent::NewUser { .. } => "new_user",
+            DbEvent::Vote { .. } => "vote",
+            DbEvent::Unvote { .. } => "unvote",
+            DbEvent::NewDinner { .. } => "new_dinner",
+            DbEvent::EditShortname { .. } => "edit_shortname",
+            DbEvent::EditLongname { .. } => "edit_longname",
+            DbEvent::EditDetails { .. } => "edit_details",
+            DbEvent::EditDinner { .. } => "edit_dinner",
+            DbEvent::EditPhoto { .. } => "edit_photo",
+            DbEvent::DeleteDinner { .. } => "delete_dinner",
+            DbEvent::SetRating { .. } => "set_rating",
+            DbEvent::ClearRating { .. } => "clear_rating",
+            DbEvent::Serve { .. } => "serve",
+            DbEvent::SetVotes { .. } => "set_votes",
+            DbEvent::SetPassword { .. } => "set_password",
+            DbEvent::Setup { .. } => "setup",
+            DbEvent::SetRole { .. } => "set_role",
+            DbEvent::RenamePerson { .. } => "rename_person",
+            DbEvent::RemovePerson { .. } => "remove_person",
+            DbEvent::Deactivate { .. } => "deactivate",
+            DbEvent::Reactivate { .. } => "reactivate",
+            DbEvent::NewInvite { .. } => "new_invite",
+            DbEvent::DeleteInvite { .. } => "delete_invite",
+        }
+    }
+}
+
+// Where the database thread sends the outcome of an event
+type Reply = async_std::channel::Sender<Outcome>;
+
+fn database_thread(
+    database: std::sync::Arc<Database>,
+    recv: std::sync::mpsc::Receiver<(DbEvent, Reply)>,
+) {
+    while let Ok((event, reply)) = recv.recv() {
+        let outcome = database.update(event);
+        // The requester may have gone away, that's fine.
+        let _ = reply.try_send(outcome);
+    }
+}
+
+// Make a change to the database, `at` is the time in seconds since the Unix
+// epoch.
+pub fn apply_event(db: &mut DatabaseData, event: DbEvent, at: u64) -> Outcome {
+    if let Err(outcome) = roles::check_event(db, &event) {
+        return outcome;
+    }
+    match event {
+        DbEvent::NewUser {
+            name,
+            votes,
+            password,
+            invite,
+            invite_needed,
+        } => {
+            if !valid_name(&name) {
+                return Outcome::rejected(Rejection::InvalidName);
+            }
+            // Add person if they're not already in the system.
+            if db.people.contains_key(&name) {
+                return Outcome::rejected(Rejection::NameTaken);
+            }
+            let (role, votes) = match invite {
+                Some(code) => {
+                    let code = invites::normalize(&code);
+                    let Some(invite) = db
+                        .invites
+                        .get_mut(&code)
+                        .filter(|invite| invite.is_valid(at))
+                    else {
+                        return Outcome::rejected(Rejection::WrongInvite);
+                    };
+                    invite.uses -= 1;
+                    let granted = (invite.role, invite.votes);
+                    if invite.uses == 0 {
+                        db.invites.remove(&code);
+                    }
+                    granted
+                }
+                // Only the first account, which is the owner's, needs no
+                // invite
+                None if invite_needed && !db.people.is_empty() => {
+                    return Outcome::rejected(Rejection::NoInvite);
+                }
+                None => (Role::Member, votes),
+            };
+            db.people.insert(
+                name,
+                Person {
+                    votes,
+                    // The first one runs the server
+                    role: if db.people.is_empty() {
+                        Role::Owner
+                    } else {
+                        role
+                    },
+                    password,
+                    deactivated: false,
+                },
+            );
+            Outcome::Applied
+        }
+        DbEvent::Vote { user, index } => {
+            let Some(person) = db.people.get_mut(&user) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            let Some((id, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            if dinner.vote.is_some() {
+                return Outcome::rejected(Rejection::AlreadyVoted);
+            }
+            if person.votes == 0 {
+                return Outcome::rejected(Rejection::NoVotesLeft);
+            }
+            dinner.vote = Some(user.clone());
+            if !person.role.can(Permission::VoteForFree) {
+                person.votes -= 1;
+            }
+            db.history.push(HistoryEvent {
+                person: user,
+                dinner: id,
+                kind: HistoryKind::Vote,
+                at,
+            });
+            Outcome::Applied
+        }
+        DbEvent::Unvote { index, .. } => {
+            let Some((id, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            let Some(voter) = dinner.vote.take() else {
+                return Outcome::rejected(Rejection::NotVoted);
+            };
+            // The vote goes back to whoever spent it
+            if let Some(person) = db.people.get_mut(&voter) {
+                if !person.role.can(Permission::VoteForFree) {
+                    person.votes = person.votes.saturating_add(1);
+                }
+            }
+            db.history.push(HistoryEvent {
+                person: voter,
+                dinner: id,
+                kind: HistoryKind::Unvote,
+                at,
+            });
+            Outcome::Applied
+        }
+        DbEvent::NewDinner { name, .. } => {
+            if !valid_name(&name) {
+                return Outcome::rejected(Rejection::InvalidName);
+            }
+            // Add dinner if it's not already in the system.
+            if dinner_named(&db.dinners, &name).is_some() {
+                return Outcome::rejected(Rejection::NameTaken);
+            }
+            let id = db.next_dinner;
+            db.next_dinner += 1;
+            db.dinners.insert(
+                id,
+                Dinner {
+                    short: "-".to_string(),
+                    long: "-".to_string(),
+                    photo: None,
+                    vote: None,
+                    ratings: Vec::new(),
+                    name,
+                },
+            );
+            Outcome::Created { id }
+        }
+        DbEvent::EditShortname { index, name, .. } => {
+            let Some(id) = db.find_dinner(&index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            if !valid_name(&name) {
+                return Outcome::rejected(Rejection::InvalidName);
+            }
+            if dinner_named(&db.dinners, &name).is_some_and(|other| other != id)
+            {
+                return Outcome::rejected(Rejection::NameTaken);
+            }
+            if let Some(dinner) = db.dinners.get_mut(&id) {
+                dinner.name = name;
+            }
+            Outcome::Applied
+        }
+        DbEvent::EditLongname { index, name, .. } => {
+            let Some((_, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            dinner.short = name;
+            Outcome::Applied
+        }
+        DbEvent::EditDetails { index, name, .. } => {
+            let Some((_, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            dinner.long = name;
+            Outcome::Applied
+        }
+        DbEvent::EditDinner {
+            index,
+            name,
+            short,
+            long,
+            ..
+        } => {
+            let Some(id) = db.find_dinner(&index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            if let Some(name) = &name {
+                if !valid_name(name) {
+                    return Outcome::rejected(Rejection::InvalidName);
+                }
+                if dinner_named(&db.dinners, name)
+                    .is_some_and(|other| other != id)
+                {
+                    return Outcome::rejected(Rejection::NameTaken);
+                }
+            }
+            let Some(dinner) = db.dinners.get_mut(&id) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            if let Some(name) = name {
+                dinner.name = name;
+            }
+            if let Some(short) = short {
+                dinner.short = short;
+            }
+            if let Some(long) = long {
+                dinner.long = long;
+            }
+            Outcome::Applied
+        }
+        DbEvent::EditPhoto { index, photo, .. } => {
+            let Some((_, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            dinner.photo = photo;
+            Outcome::Applied
+        }
+        DbEvent::DeleteDinner { index, .. } => match db.find_dinner(&index) {
+            Some(id) => {
+                db.dinners.remove(&id);
+                Outcome::Applied
+            }
+            None => Outcome::not_found(Missing::Dinner),
+        },
+        DbEvent::SetRating {
+            user,
+            index,
+            stars,
+            note,
+        } => {
+            let Some((_, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            let rating = Rating {
+                person: user,
+                stars,
+                note,
+            };
+            if !rating.is_valid() {
+                return Outcome::rejected(Rejection::InvalidRating);
+            }
+            dinner.rate(rating);
+            Outcome::Applied
+        }
+        DbEvent::ClearRating { user, index } => {
+            let Some((_, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            if !dinner.unrate(&user) {
+                return Outcome::rejected(Rejection::NotRated);
+            }
+            Outcome::Applied
+        }
+        DbEvent::Serve { index, .. } => {
+            let Some((id, dinner)) = dinner_mut(&mut db.dinners, &index) else {
+                return Outcome::not_found(Missing::Dinner);
+            };
+            // The winning vote is used up, not given back
+            let Some(voter) = dinner.vote.take() else {
+                return Outcome::rejected(Rejection::NotVoted);
+            };
+            db.history.push(HistoryEvent {
+                person: voter,
+                dinner: id,
+                kind: HistoryKind::Serve,
+                at,
+            });
+            Outcome::Applied
+        }
+        DbEvent::SetVotes { votes, .. } => {
+            // Guests keep the votes they were given when they joined
+            for person in db.people.values_mut() {
+                if person.role != Role::Guest {
+                    person.votes = votes;
+                }
+            }
+            Outcome::Applied
+        }
+        DbEvent::SetPassword { name, hash, .. } => {
+            let Some(person) = db.people.get_mut(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            person.password = Some(hash);
+            Outcome::Applied
+        }
+        DbEvent::Setup { name, password } => {
+            if db.people.values().any(|person| person.role == Role::Owner) {
+                return Outcome::rejected(Rejection::AlreadySetUp);
+            }
+            let Some(person) = db.people.get_mut(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            // The owner has to be able to log in
+            if person.deactivated {
+                return Outcome::rejected(Rejection::Deactivated);
+            }
+            person.role = Role::Owner;
+            if password.is_some() {
+                person.password = password;
+            }
+            Outcome::Applied
+        }
+        DbEvent::SetRole { name, role, .. } => {
+            let Some(person) = db.people.get(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            let (current, deactivated) = (person.role, person.deactivated);
+            if role == Role::Owner {
+                // The owner has to be able to log in
+                if deactivated {
+                    return Outcome::rejected(Rejection::Deactivated);
+                }
+                // The old owner stays an admin
+                for person in db.people.values_mut() {
+                    if person.role == Role::Owner {
+                        person.role = Role::Admin;
+                    }
+                }
+            } else if current == Role::Owner {
+                return Outcome::rejected(Rejection::IsOwner);
+            } else if current.is_admin()
+                && !role.is_admin()
+                && !deactivated
+                && roles::admins(db) == 1
+            {
+                return Outcome::rejected(Rejection::LastAdmin);
+            }
+            if let Some(person) = db.people.get_mut(&name) {
+                person.role = role;
+            }
+            Outcome::Applied
+        }
+        DbEvent::RenamePerson { name, new_name, .. } => {
+            if !db.people.contains_key(&name) {
+                return Outcome::not_found(Missing::Person);
+            }
+            if !valid_name(&new_name) {
+                return Outcome::rejected(Rejection::InvalidName);
+            }
+            if db.people.contains_key(&new_name) {
+                return Outcome::rejected(Rejection::NameTaken);
+            }
+            db.rename_person(&name, &new_name);
+            Outcome::Applied
+        }
+        DbEvent::RemovePerson { name, .. } => {
+            let Some(person) = db.people.get(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            if person.role == Role::Owner {
+                return Outcome::rejected(Rejection::IsOwner);
+            }
+            if person.role.is_admin()
+                && !person.deactivated
+                && roles::admins(db) == 1
+            {
+                return Outcome::rejected(Rejection::LastAdmin);
+            }
+            db.remove_person(&name);
+            Outcome::Applied
+        }
+        DbEvent::Deactivate { name, .. } => {
+            let Some(person) = db.people.get(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            if person.role == Role::Owner {
+                return Outcome::rejected(Rejection::IsOwner);
+            }
+            if person.role.is_admin()
+                && !person.deactivated
+                && roles::admins(db) == 1
+            {
+                return Outcome::rejected(Rejection::LastAdmin);
+            }
+            db.release_votes(&name);
+            if let Some(person) = db.people.get_mut(&name) {
+                person.deactivated = true;
+            }
+            Outcome::Applied
+        }
+        DbEvent::Reactivate { name, .. } => {
+            let Some(person) = db.people.get_mut(&name) else {
+                return Outcome::not_found(Missing::Person);
+            };
+            person.deactivated = false;
+            Outcome::Applied
+        }
+        DbEvent::NewInvite {
+            user,
+            code,
+            role,
+            votes,
+            uses,
+            expires,
+        } => {
+            // There's only one owner, see `SetRole`
+            if role == Role::Owner || uses == 0 {
+                return Outcome::rejected(Rejection::InvalidInvite);
+            }
+            db.invites.retain(|_, invite| invite.is_valid(at));
+            let invite = Invite {
+                role,
+                votes,
+                uses,
+                expires,
+                by: user,
+            };
+            db.invites.insert(invites::normalize(&code), invite);
+            Outcome::Applied
+        }
+        DbEvent::DeleteInvite { code, .. } => {
+            if db.invites.remove(&invites::normalize(&code)).is_none() {
+                return Outcome::not_found(Missing::Invite);
+            }
+            Outcome::Applied
+        }
+    }
+}
+
+// Whether a person or dinner can be called `name`.  Backslashes separate
+// arguments and line breaks separate lines in the legacy protocol, so a name
+// with them couldn't be used.
+fn valid_name(name: &str) -> bool {
+    !name.trim().is_empty()
+        && !name.chars().any(|c| c == '\\' || c.is_control())
+}
+
+#[derive(Clone)]
+pub struct Server {
+    send: Arc<Mutex<std::sync::mpsc::Sender<(DbEvent, Reply)>>>,
+    database: Arc<Database>,
+    backups: Arc<Backups>,
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    // Token for making someone the owner, while there isn't one
+    setup: Arc<Mutex<Option<String>>>,
+}
+
+impl Server {
+    // Send an event to the database thread, and wait until it's handled.
+    // `token` is the session it was sent with, which must belong to whoever
+    // is making the change.
+    async fn apply(
+        &self,
+        token: Option<&str>,
+        event: DbEvent,
+    ) -> Result<Outcome> {
+        if let Err(outcome) = self.authorize(token, &event) {
+            return Ok(outcome);
+        }
+        let (reply, outcome) = async_std::channel::bounded(1);
+        let applied = event.clone();
+        let stopped = || {
+            tide::Error::from_str(
+                tide::StatusCode::InternalServerError,
+                "database thread stopped",
+            )
+        };
+
+        self.send
+            .lock()
+            .unwrap()
+            .send((event, reply))
+            .map_err(|_| stopped())?;
+        let outcome = outcome.recv().await.map_err(|_| stopped())?;
+        if outcome.is_applied() {
+            self.sessions.update(&applied, token);
+        }
+        Ok(outcome)
+    }
+
+    fn authorize(
+        &self,
+        token: Option<&str>,
+        event: &DbEvent,
+    ) -> std::result::Result<(), Outcome> {
+        if self.config.legacy_unauthenticated {
+            return Ok(());
+        }
+        match event {
+            // Everyone needs a way to log in.  Missing the invite is checked
+            // when it's applied, this only picks which to say is wrong.
+            DbEvent::NewUser {
+                password: None,
+                invite,
+                ..
+            } => {
+                let first =
+                    self.database.data.lock().unwrap().people.is_empty();
+                Err(Outcome::rejected(if invite.is_none() && !first {
+                    Rejection::NoInvite
+                } else {
+                    Rejection::InvalidPassword
+                }))
+            }
+            _ => match event.actor() {
+                Some(user) => self.check_login(token, user),
+                None => Ok(()),
+            },
+        }
+    }
+
+    // Make sure `token` is a session of `user`, unless logging in is off.
+    fn check_login(
+        &self,
+        token: Option<&str>,
+        user: &str,
+    ) -> std::result::Result<(), Outcome> {
+        if self.config.legacy_unauthenticated {
+            return Ok(());
+        }
+        match token.and_then(|token| self.sessions.person(token)) {
+            Some(person) if person == user => Ok(()),
+            Some(_) => Err(Outcome::rejected(Rejection::WrongUser)),
+            None => Err(Outcome::rejected(Rejection::NotLoggedIn)),
+        }
+    }
+}
+
+async fn handle_event(
+    mut request: tide::Request<Server>,
+) -> Result<tide::Response> {
+    let command = match request.body_string().await {
+        Ok(post) => Command::parse(&post),
+        Err(e) => return Ok(bad_request(e)),
+    };
+    let command = match command {
+        Ok(command) => command,
+        Err(e) => {
+            tide::log::warn!("Bad POST: {}", e);
+            return Ok(bad_request(e));
+        }
+    };
+    let state = request.state();
+    let mut out = String::new();
+
+    let event = match command {
+        Command::List => {
+            // Old clients expect names, not IDs
+            for value in state.database.data.lock().unwrap().dinners.values() {
+                out.push_str(&value.name);
+                out.push('\\');
+                out.push_str(&value.short);
+                if let Some(ref user) = value.vote {
+                    out.push('\\');
+                    out.push_str(user);
+                }
+                out.push('\n');
+            }
+            out.pop();
+            return Ok(out.into());
+        }
+        Command::Get { index } => {
+            if let Some(details) =
+                state.database.data.lock().unwrap().dinner(&index)
+            {
+                out.push_str(&details.short);
+                out.push('\r');
+                out.push_str(&details.long);
+                out.push_str("\r\r");
+                // Ratings: count, mean, then how many gave 1 to 5 stars
+                let ratings = details.rating_summary();
+                out.push_str(&ratings.count.to_string());
+                out.push('\\');
+                if let Some(mean) = ratings.mean {
+                    out.push_str(&format!("{mean:.1}"));
+                }
+                for count in ratings.distribution {
+                    out.push('\\');
+                    out.push_str(&count.to_string());
+                }
+            }
+            return Ok(out.into());
+        }
+        Command::GetVotes { user } => {
+            if let Some(person) =
+                state.database.data.lock().unwrap().people.get(&user)
+            {
+                out.push_str(&person.votes.to_string());
+                out.push('\\');
+                let admin = person.role.is_admin();
+                out.push_str(if admin { "TRUE" } else { "FALSE" });
+            }
+            return Ok(out.into());
+        }
+        Command::Vote { user, index } => DbEvent::Vote { user, index },
+        Command::Unvote { user, index } => DbEvent::Unvote { user, index },
+        Command::ViewVotes { user } => {
+            let logged_in =
+                state.check_login(auth::bearer(&request), &user).is_ok();
+            let Some(summary) = state
+                .database
+                .data
+                .lock()
+                .unwrap()
+                .vote_summary(&user, logged_in)
+            else {
+                return Ok(reply(Outcome::not_found(Missing::Person)));
+            };
+            // First line is the votes left and dinners voted for, then for
+            // admins one line per person starting with their name.
+            out.push_str(&summary.mine.votes.to_string());
+            for dinner in &summary.mine.dinners {
+                out.push('\\');
+                out.push_str(dinner);
+            }
+            for person in summary.everyone.iter().flatten() {
+                out.push('\n');
+                out.push_str(&person.name);
+                out.push('\\');
+                out.push_str(&person.votes.to_string());
+                for dinner in &person.dinners {
+                    out.push('\\');
+                    out.push_str(dinner);
+                }
+            }
+            return Ok(out.into());
+        }
+        // Old clients can't send a password, so this only works with
+        // logging in turned off
+        Command::NewUser { name, invite } => DbEvent::NewUser {
+            name,
+            votes: state.config.default_votes,
+            password: None,
+            invite,
+            invite_needed: !state.config.legacy_unauthenticated,
+        },
+        Command::NewDinner { user, name } => DbEvent::NewDinner { user, name },
+        Command::EditShortname { user, index, name } => {
+            DbEvent::EditShortname { user, index, name }
+        }
+        Command::EditLongname { user, index, name } => {
+            DbEvent::EditLongname { user, index, name }
+        }
+        Command::EditDetails { user, index, name } => {
+            DbEvent::EditDetails { user, index, name }
+        }
+        Command::DeleteDinner { user, index } => {
+            DbEvent::DeleteDinner { user, index }
+        }
+        Command::SetRating {
+            user,
+            index,
+            stars,
+            note,
+        } => DbEvent::SetRating {
+            user,
+            index,
+            stars,
+            note,
+        },
+        Command::ClearRating { user, index } => {
+            DbEvent::ClearRating { user, index }
+        }
+        Command::ViewAnalytics { user, index } => {
+            let db = state.database.data.lock().unwrap();
+            if !db.people.contains_key(&user) {
+                return Ok(reply(Outcome::not_found(Missing::Person)));
+            }
+            let now = history::now();
+            // One line per dinner ("d"), week of vote share ("w") and voter
+            // ("p"), each starting with that letter.
+            let (dinners, share, voters) = match index {
+                Some(index) => {
+                    let Some(analytics) = db.dinner_analytics(&index, now)
+                    else {
+                        return Ok(reply(Outcome::not_found(Missing::Dinner)));
+                    };
+                    (vec![analytics.stats], analytics.share, analytics.voters)
+                }
+                None => {
+                    let analytics = db.analytics(now);
+                    (analytics.dinners, Vec::new(), analytics.voters)
+                }
+            };
+            let mut lines = Vec::new();
+            for dinner in dinners {
+                lines.push(format!(
+                    "d\\{}\\{}\\{}\\{}\\{}",
+                    dinner.name,
+                    dinner.wins,
+                    dinner.votes,
+                    dinner
+                        .mean_rating
+                        .map_or(String::new(), |mean| { format!("{mean:.1}") }),
+                    dinner
+                        .last_eaten
+                        .map_or(String::new(), |at| at.to_string()),
+                ));
+            }
+            for point in share {
+                lines.push(format!(
+                    "w\\{}\\{}\\{:.2}",
+                    point.week, point.votes, point.share
+                ));
+            }
+            for voter in voters {
+                lines.push(format!(
+                    "p\\{}\\{}\\{}\\{}",
+                    voter.name,
+                    voter.votes,
+                    voter.current_streak,
+                    voter.longest_streak
+                ));
+            }
+            return Ok(lines.join("\n").into());
+        }
+        Command::Serve { user, index } => DbEvent::Serve { user, index },
+        Command::SetVotes { user, votes } => DbEvent::SetVotes { user, votes },
+        Command::RenamePerson {
+            user,
+            name,
+            new_name,
+        } => DbEvent::RenamePerson {
+            user,
+            name,
+            new_name,
+        },
+        Command::RemovePerson { user, name } => {
+            DbEvent::RemovePerson { user, name }
+        }
+        Command::Deactivate { user, name } => {
+            DbEvent::Deactivate { user, name }
+        }
+        Command::Reactivate { user, name } => {
+            DbEvent::Reactivate { user, name }
+        }
+    };
+
+    Ok(reply(state.apply(auth::bearer(&request), event).await?))
+}
+
+// Reply with the outcome of a command.
+fn reply(outcome: Outcome) -> tide::Response {
+    // Existing clients expect an empty `200 OK` on success.
+    if outcome.is_applied() {
+        return tide::Response::new(tide::StatusCode::Ok);
+    }
+    let mut response = tide::Response::new(outcome.status());
+    response.set_body(outcome.to_string());
+    response
+}
+
+// Reply to a message that couldn't be understood.
+fn bad_request(reason: impl std::fmt::Display) -> tide::Response {
+    let mut response = tide::Response::new(tide::StatusCode::BadRequest);
+    response.set_body(reason.to_string());
+    response
+}
+
+// Notifications sent through server sent events.
+async fn sse_notify(
+    request: tide::Request<Server>,
+    sender: tide::sse::Sender,
+) -> Result<()> {
+    let config = &request.state().config;
+    loop {
+        sender.send("notify", &config.notify_message, None).await?;
+        if config.notify_every == 0 {
+            return Ok(());
+        }
+        async_std::task::sleep(std::time::Duration::from_secs(
+            config.notify_every,
+        ))
+        .await;
+    }
+}
+
+// Open the database and set up the routes, ready to listen.
+pub fn build_app(config: Config) -> std::io::Result<tide::Server<Server>> {
+    let database = Arc::new(Database::open(
+        config.storage.open(&config.data_dir)?,
+        &config.data_dir,
+    )?);
+    let backups = Arc::new(Backups::new(
+        config.data_dir.join("backups"),
+        config.backup_keep,
+    ));
+    if config.backup_every != 0 {
+        let backups = backups.clone();
+        let database = database.clone();
+        let every = std::time::Duration::from_secs(config.backup_every);
+        std::thread::spawn(move || backups::schedule(backups, database, every));
+    }
+
+    if config.legacy_unauthenticated {
+        tide::log::warn!("Logging in is off, anyone can act as anyone");
+    }
+    // With nobody yet, the first account will be the owner
+    let needs_owner = {
+        let people = &database.data.lock().unwrap().people;
+        !people.is_empty()
+            && !people.values().any(|person| person.role == Role::Owner)
+    };
+    let setup = if !needs_owner {
+        auth::setup_done(&config.data_dir);
+        None
+    } else {
+        Some(auth::new_setup_token(&config.data_dir)?)
+    };
+
+    let (send, recv) = std::sync::mpsc::channel();
+    let server = Server {
+        send: Arc::new(Mutex::new(send)),
+        database: database.clone(),
+        backups,
+        sessions: Arc::new(Sessions::new(config.session_ttl)),
+        setup: Arc::new(Mutex::new(setup)),
+        config: Arc::new(config),
+    };
+    std::thread::spawn(move || database_thread(database, recv));
+
+    let mut app = tide::with_state(server);
+    app.at("/meal_vote").post(handle_event);
+    app.at("/meal_vote/sse").get(sse::endpoint(sse_notify));
+    api::routes(&mut app);
+    Ok(app)
+}
@@ -0,0 +1,132 @@
+// Upgrades for database files written by older versions of the server.
+//
+// Versions:
+//  1. Dinners keyed by name, history refers to dinners by name (no
+//     `version`, no `next_dinner`)
+//  2. Dinners keyed by generated ID, with the name as a field
+//  3. People have a role, not just whether they're an admin
+
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{roles::Role, storage, DatabaseDataSerde, DinnerId};
+
+// Version written by this server
+pub const VERSION: u32 = 3;
+
+// Upgrades the stored data by one version, returning what it changed
+type Migration = fn(&mut DatabaseDataSerde) -> Vec<String>;
+
+// `MIGRATIONS[n]` upgrades version `n + 1` to `n + 2`
+const MIGRATIONS: [Migration; VERSION as usize - 1] =
+    [add_dinner_ids, add_roles];
+
+// Which version `data` was written as.
+pub(crate) fn version(data: &DatabaseDataSerde) -> u32 {
+    match data.version {
+        Some(version) => version,
+        // From before the version was written
+        None if data.next_dinner.is_some() => 2,
+        None => 1,
+    }
+}
+
+// Upgrade `data` to the current version, returning what changed.
+pub(crate) fn migrate(data: &mut DatabaseDataSerde) -> io::Result<Vec<String>> {
+    let version = version(data);
+    if version == 0 || version > VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "the database is version {version}, but this server only \
+                    knows versions 1 to {VERSION}"
+            ),
+        ));
+    }
+
+    let mut changes = Vec::new();
+    for (from, migration) in
+        MIGRATIONS.iter().enumerate().skip(version as usize - 1)
+    {
+        for change in migration(data) {
+            changes.push(format!("{} to {}: {change}", from + 1, from + 2));
+        }
+    }
+    data.version = Some(VERSION);
+
+    Ok(changes)
+}
+
+// Print what migrating the muon file at `path` would change, without
+// changing anything.
+pub fn dry_run(path: &Path) -> io::Result<()> {
+    let Some(mut data) = storage::read_muon(path)? else {
+        println!("There's no database at {}", path.display());
+        return Ok(());
+    };
+    let version = version(&data);
+    let changes = migrate(&mut data)?;
+
+    if changes.is_empty() {
+        println!("{} is version {version}, nothing to do", path.display());
+    } else {
+        println!(
+            "{} is version {version}, migrating to version {VERSION} would:",
+            path.display(),
+        );
+        for change in changes {
+            println!("- {change}");
+        }
+    }
+
+    Ok(())
+}
+
+// Give every dinner an ID, and refer to dinners by ID in the history.
+fn add_dinner_ids(data: &mut DatabaseDataSerde) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut ids = HashMap::<String, DinnerId>::new();
+
+    for (id, dinner) in (0..).zip(&mut data.dinners) {
+        let name = std::mem::replace(&mut dinner.key, id.to_string());
+        changes.push(format!("give dinner `{name}` ID {id}"));
+        ids.insert(name.clone(), id);
+        dinner.name = Some(name);
+    }
+    data.next_dinner = Some(ids.len() as DinnerId);
+
+    // Deleted dinners can't be told apart, so their history is dropped
+    let before = data.history.len();
+    data.history
+        .retain_mut(|event| match ids.get(&event.dinner) {
+            Some(id) => {
+                event.dinner = id.to_string();
+                true
+            }
+            None => false,
+        });
+    let dropped = before - data.history.len();
+    if dropped != 0 {
+        changes
+            .push(format!("drop {dropped} history events for deleted dinners"));
+    }
+
+    changes
+}
+
+// Give everyone a role: admins stay admins, and everyone else is a member.
+fn add_roles(data: &mut DatabaseDataSerde) -> Vec<String> {
+    let mut changes = Vec::new();
+    for person in &mut data.people {
+        if person.value.role.is_none() {
+            let role = Role::from_admin(person.value.admin);
+            changes.push(format!(
+                "make `{}` {}",
+                person.key,
+                String::from(role),
+            ));
+            person.value.role = Some(role);
+        }
+    }
+    changes.sort();
+    changes
+}
@@ -0,0 +1,199 @@
+// What happened to a `DbEvent` sent to the database thread.
+
+use std::fmt;
+
+use serde::Serialize;
+use tide::StatusCode;
+
+use crate::DinnerId;
+
+// Outcome of a database event
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    // The change was made
+    Applied,
+    // A new dinner was made
+    Created { id: DinnerId },
+    // The change was refused
+    Rejected { reason: Rejection },
+    // Something the event refers to doesn't exist
+    NotFound { missing: Missing },
+    // The change couldn't be saved, so it wasn't made
+    NotSaved,
+}
+
+// Why an event was refused
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    // Only admins may do this
+    NotAdmin,
+    // The person's role doesn't allow this
+    NotAllowed,
+    // Only the owner may do this
+    NotOwner,
+    // The owner has to hand over ownership before losing it
+    IsOwner,
+    // There has to be an owner or admin left
+    LastAdmin,
+    // There's already an owner, so the setup token can't be used
+    AlreadySetUp,
+    // Wrong setup token, or it was used already
+    WrongSetupToken,
+    // The person has no votes left to spend
+    NoVotesLeft,
+    // Someone already voted for this dinner
+    AlreadyVoted,
+    // Nobody voted for this dinner
+    NotVoted,
+    // The vote belongs to someone else
+    NotYourVote,
+    // The name is already used
+    NameTaken,
+    // The name is blank, or has a backslash or control character
+    InvalidName,
+    // Stars out of range, or the note is too long
+    InvalidRating,
+    // The person hasn't rated this dinner
+    NotRated,
+    // No session token, or it expired
+    NotLoggedIn,
+    // The session token belongs to someone else
+    WrongUser,
+    // The person was deactivated by an admin
+    Deactivated,
+    // Wrong name or password when logging in
+    WrongPassword,
+    // Too many wrong passwords lately
+    LockedOut,
+    // The password is too short, or the PIN isn't all digits
+    InvalidPassword,
+    // A new account needs an invite code
+    NoInvite,
+    // The invite code doesn't exist, was used up or expired
+    WrongInvite,
+    // An invite for the owner, or for no accounts
+    InvalidInvite,
+}
+
+// What an event referred to that doesn't exist
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Missing {
+    Person,
+    Dinner,
+    Backup,
+    Invite,
+}
+
+impl Outcome {
+    pub fn rejected(reason: Rejection) -> Self {
+        Outcome::Rejected { reason }
+    }
+
+    pub fn not_found(missing: Missing) -> Self {
+        Outcome::NotFound { missing }
+    }
+
+    pub fn is_applied(self) -> bool {
+        matches!(self, Outcome::Applied | Outcome::Created { .. })
+    }
+
+    // HTTP status to reply with.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Outcome::Applied => StatusCode::Ok,
+            Outcome::Created { .. } => StatusCode::Created,
+            Outcome::Rejected {
+                reason:
+                    Rejection::NotAdmin
+                    | Rejection::NotAllowed
+                    | Rejection::NotOwner
+                    | Rejection::WrongUser
+                    | Rejection::Deactivated
+                    | Rejection::NoInvite
+                    | Rejection::WrongInvite,
+            } => StatusCode::Forbidden,
+            Outcome::Rejected {
+                reason:
+                    Rejection::NotLoggedIn
+                    | Rejection::WrongPassword
+                    | Rejection::WrongSetupToken,
+            } => StatusCode::Unauthorized,
+            Outcome::Rejected {
+                reason: Rejection::LockedOut,
+            } => StatusCode::TooManyRequests,
+            Outcome::Rejected {
+                reason:
+                    Rejection::InvalidRating
+                    | Rejection::InvalidName
+                    | Rejection::InvalidPassword
+                    | Rejection::InvalidInvite,
+            } => StatusCode::UnprocessableEntity,
+            Outcome::Rejected { .. } => StatusCode::Conflict,
+            Outcome::NotFound { .. } => StatusCode::NotFound,
+            Outcome::NotSaved => StatusCode::InternalServerError,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Applied => write!(f, "applied"),
+            Outcome::Created { id } => write!(f, "created {id}"),
+            Outcome::Rejected { reason } => write!(f, "rejected: {reason}"),
+            Outcome::NotFound { missing } => write!(f, "no such {missing}"),
+            Outcome::NotSaved => write!(f, "couldn't save the change"),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::NotAdmin => "only admins can do that",
+            Rejection::NotAllowed => "your role can't do that",
+            Rejection::NotOwner => "only the owner can do that",
+            Rejection::IsOwner => "the owner has to hand over ownership first",
+            Rejection::LastAdmin => "there has to be an admin left",
+            Rejection::AlreadySetUp => "there's already an owner",
+            Rejection::WrongSetupToken => "wrong or used setup token",
+            Rejection::NoVotesLeft => "no votes left",
+            Rejection::AlreadyVoted => "dinner already has a vote",
+            Rejection::NotVoted => "dinner has no vote",
+            Rejection::NotYourVote => "vote belongs to someone else",
+            Rejection::NameTaken => "name is already taken",
+            Rejection::InvalidName => {
+                "name is blank or has a backslash or control character"
+            }
+            Rejection::InvalidRating => "stars out of range or note too long",
+            Rejection::NotRated => "dinner wasn't rated",
+            Rejection::NotLoggedIn => "not logged in",
+            Rejection::WrongUser => "logged in as someone else",
+            Rejection::Deactivated => "that account is deactivated",
+            Rejection::WrongPassword => "wrong name or password",
+            Rejection::LockedOut => "too many wrong passwords, try later",
+            Rejection::InvalidPassword => {
+                "password needs 8 characters, or a PIN 4 to 8 digits"
+            }
+            Rejection::NoInvite => "an invite code is needed",
+            Rejection::WrongInvite => "wrong, used up or expired invite code",
+            Rejection::InvalidInvite => {
+                "invites are for at least one account, and not the owner"
+            }
+        })
+    }
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Missing::Person => "person",
+            Missing::Dinner => "dinner",
+            Missing::Backup => "backup",
+            Missing::Invite => "invite",
+        })
+    }
+}
@@ -0,0 +1,64 @@
+// Dinner photos: what's accepted and how they're served.
+
+// Largest photo accepted, in bytes
+pub(crate) const MAX_PHOTO_BYTES: usize = 4 * 1024 * 1024;
+
+// Figure out the image type from the first bytes, `None` if it isn't one we
+// accept.
+pub(crate) fn sniff(photo: &[u8]) -> Option<&'static str> {
+    if photo.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if photo.starts_with(b"\x89PNG\r\n\x1A\n") {
+        Some("image/png")
+    } else if photo.starts_with(b"GIF87a") || photo.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if photo.len() >= 12
+        && photo.starts_with(b"RIFF")
+        && &photo[8..12] == b"WEBP"
+    {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+// Entity tag for caching, a hash of the photo (FNV-1a, so it's the same
+// across restarts).
+pub(crate) fn etag(photo: &[u8]) -> String {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in photo {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+
+    format!("\"{hash:016x}\"")
+}
+
+// Serialize an optional photo as base64 instead of a list of numbers.
+pub(crate) mod base64 {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        photo: &Option<Arc<[u8]>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match photo {
+            Some(photo) => serializer.serialize_some(&::base64::encode(photo)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Arc<[u8]>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|photo| {
+                ::base64::decode(photo)
+                    .map(Arc::from)
+                    .map_err(serde::de::Error::custom)
+            })
+            .transpose()
+    }
+}
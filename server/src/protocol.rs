@@ -0,0 +1,466 @@
+// Legacy single-letter POST protocol.
+//
+// A message is a command letter, then a space, then the arguments separated
+// by backslashes.  The last argument is everything after the previous
+// separator, so it may contain backslashes itself.
+
+use std::fmt;
+
+// Where photos are uploaded now
+const PHOTO_ROUTE: &str = "PUT /api/dinners/{name}/photo?user={user}";
+
+// A parsed legacy protocol message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // "l" => Get entire list of dinner options
+    List,
+    // "g {}" => Get details for a specific dinner option
+    Get {
+        index: String,
+    },
+    // "v {}\\{}" => Vote
+    Vote {
+        user: String,
+        index: String,
+    },
+    // "u {}\\{}" => Revoke Vote
+    Unvote {
+        user: String,
+        index: String,
+    },
+    // "a {}" => View all votes
+    ViewVotes {
+        user: String,
+    },
+    // "c {}\\{?}" => Create account, with an invite code
+    NewUser {
+        name: String,
+        invite: Option<String>,
+    },
+    // "n {}\\{}" => New dinner option
+    NewDinner {
+        user: String,
+        name: String,
+    },
+    // "s {}\\{}\\{}" => Edit shortname
+    EditShortname {
+        user: String,
+        index: String,
+        name: String,
+    },
+    // "t {}\\{}\\{}" => Edit title / longname
+    EditLongname {
+        user: String,
+        index: String,
+        name: String,
+    },
+    // "m {}\\{}\\{}" => Edit More details
+    EditDetails {
+        user: String,
+        index: String,
+        name: String,
+    },
+    // "d {}\\{}" => Delete dinner option
+    DeleteDinner {
+        user: String,
+        index: String,
+    },
+    // "r {}\\{}\\{}\\{?}" => Set rating (1 to 5 stars, and a note)
+    SetRating {
+        user: String,
+        index: String,
+        stars: u8,
+        note: Option<String>,
+    },
+    // "r {}\\{}\\0" => Clear rating
+    ClearRating {
+        user: String,
+        index: String,
+    },
+    // "y {}\\{?}" => View analytics
+    ViewAnalytics {
+        user: String,
+        index: Option<String>,
+    },
+    // "e {}\\{}" => Serve (eat) dinner option, its vote won
+    Serve {
+        user: String,
+        index: String,
+    },
+    // "h {}" => Get number of votes
+    GetVotes {
+        user: String,
+    },
+    // "z {}\\{}" => Set number of votes
+    SetVotes {
+        user: String,
+        votes: u16,
+    },
+    // "i {}\\{}\\{}" => Rename person (identity)
+    RenamePerson {
+        user: String,
+        name: String,
+        new_name: String,
+    },
+    // "x {}\\{}" => Delete person
+    RemovePerson {
+        user: String,
+        name: String,
+    },
+    // "f {}\\{}" => Deactivate (freeze) person
+    Deactivate {
+        user: String,
+        name: String,
+    },
+    // "w {}\\{}" => Reactivate (wake) person
+    Reactivate {
+        user: String,
+        name: String,
+    },
+}
+
+// Why a message couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // The message was empty
+    Empty,
+    // The first letter isn't a known command
+    UnknownCommand(char),
+    // The command letter wasn't followed by a space
+    MissingSpace(char),
+    // An argument was left out
+    MissingArgument {
+        command: char,
+        argument: &'static str,
+    },
+    // An argument wasn't followed by a backslash and the next argument
+    MissingSeparator {
+        command: char,
+        argument: &'static str,
+    },
+    // An argument was present, but empty
+    EmptyArgument {
+        command: char,
+        argument: &'static str,
+    },
+    // The command takes no arguments, but some were given
+    UnexpectedArguments(char),
+    // The command isn't part of this protocol anymore, use the route instead
+    Moved(char, &'static str),
+    // An argument that should be a number isn't one
+    InvalidNumber {
+        command: char,
+        argument: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty message"),
+            ParseError::UnknownCommand(c) => {
+                write!(f, "unknown command `{c}`")
+            }
+            ParseError::MissingSpace(c) => {
+                write!(f, "expected a space after command `{c}`")
+            }
+            ParseError::MissingArgument { command, argument } => {
+                write!(f, "command `{command}` is missing `{argument}`")
+            }
+            ParseError::MissingSeparator { command, argument } => write!(
+                f,
+                "command `{command}` expects `\\` and more arguments after \
+                 `{argument}`"
+            ),
+            ParseError::EmptyArgument { command, argument } => {
+                write!(f, "command `{command}` has an empty `{argument}`")
+            }
+            ParseError::UnexpectedArguments(c) => {
+                write!(f, "command `{c}` takes no arguments")
+            }
+            ParseError::Moved(c, route) => {
+                write!(f, "command `{c}` was replaced by `{route}`")
+            }
+            ParseError::InvalidNumber {
+                command,
+                argument,
+                value,
+            } => write!(
+                f,
+                "command `{command}` expects a number for `{argument}`, got \
+                 `{value}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Arguments of a message, consumed front to back
+struct Args<'a> {
+    command: char,
+    rest: Option<&'a str>,
+}
+
+impl Args<'_> {
+    // Take the next backslash-terminated argument.
+    fn next(&mut self, argument: &'static str) -> Result<String, ParseError> {
+        let command = self.command;
+        let rest = self
+            .rest
+            .take()
+            .ok_or(ParseError::MissingArgument { command, argument })?;
+        let (arg, rest) = rest
+            .split_once('\\')
+            .ok_or(ParseError::MissingSeparator { command, argument })?;
+
+        self.rest = Some(rest);
+        nonempty(command, argument, arg)
+    }
+
+    // Take everything that's left as the final argument.
+    fn last(&mut self, argument: &'static str) -> Result<String, ParseError> {
+        let command = self.command;
+        let rest = self
+            .rest
+            .take()
+            .ok_or(ParseError::MissingArgument { command, argument })?;
+
+        nonempty(command, argument, rest)
+    }
+
+    // Take the next argument, and an optional final argument after it.
+    fn last_optional(
+        &mut self,
+        argument: &'static str,
+        optional: &'static str,
+    ) -> Result<(String, Option<String>), ParseError> {
+        if self.rest.is_some_and(|rest| rest.contains('\\')) {
+            Ok((self.next(argument)?, Some(self.last(optional)?)))
+        } else {
+            Ok((self.last(argument)?, None))
+        }
+    }
+
+    // Make sure there were no arguments.
+    fn none(&self) -> Result<(), ParseError> {
+        match self.rest {
+            Some(rest) if !rest.is_empty() => {
+                Err(ParseError::UnexpectedArguments(self.command))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(
+    command: char,
+    argument: &'static str,
+    value: String,
+) -> Result<T, ParseError> {
+    value.parse().map_err(|_| ParseError::InvalidNumber {
+        command,
+        argument,
+        value,
+    })
+}
+
+fn nonempty(
+    command: char,
+    argument: &'static str,
+    arg: &str,
+) -> Result<String, ParseError> {
+    if arg.is_empty() {
+        return Err(ParseError::EmptyArgument { command, argument });
+    }
+
+    Ok(arg.to_string())
+}
+
+impl Command {
+    // Parse a POST body.
+    pub fn parse(message: &str) -> Result<Self, ParseError> {
+        let mut chars = message.chars();
+        let command = chars.next().ok_or(ParseError::Empty)?;
+        let rest = chars.as_str();
+        let rest = match rest.strip_prefix(' ') {
+            Some(rest) => Some(rest),
+            None if rest.is_empty() => None,
+            None => return Err(ParseError::MissingSpace(command)),
+        };
+        let mut args = Args { command, rest };
+
+        Ok(match command {
+            'l' => {
+                args.none()?;
+                Command::List
+            }
+            'g' => Command::Get {
+                index: args.last("index")?,
+            },
+            'v' => Command::Vote {
+                user: args.next("user")?,
+                index: args.last("index")?,
+            },
+            'u' => Command::Unvote {
+                user: args.next("user")?,
+                index: args.last("index")?,
+            },
+            'a' => Command::ViewVotes {
+                user: args.last("user")?,
+            },
+            'c' => {
+                let (name, invite) = args.last_optional("name", "invite")?;
+                Command::NewUser { name, invite }
+            }
+            'n' => Command::NewDinner {
+                user: args.next("user")?,
+                name: args.last("name")?,
+            },
+            's' => Command::EditShortname {
+                user: args.next("user")?,
+                index: args.next("index")?,
+                name: args.last("name")?,
+            },
+            't' => Command::EditLongname {
+                user: args.next("user")?,
+                index: args.next("index")?,
+                name: args.last("name")?,
+            },
+            'm' => Command::EditDetails {
+                user: args.next("user")?,
+                index: args.next("index")?,
+                name: args.last("details")?,
+            },
+            // Binary photos get mangled as text, they have their own route
+            'p' => return Err(ParseError::Moved(command, PHOTO_ROUTE)),
+            'd' => Command::DeleteDinner {
+                user: args.next("user")?,
+                index: args.last("index")?,
+            },
+            'r' => {
+                let user = args.next("user")?;
+                let index = args.next("index")?;
+                let (stars, note) = args.last_optional("stars", "note")?;
+                match (number(command, "stars", stars)?, note) {
+                    (0, None) => Command::ClearRating { user, index },
+                    (stars, note) => Command::SetRating {
+                        user,
+                        index,
+                        stars,
+                        note,
+                    },
+                }
+            }
+            'y' => {
+                let (user, index) = args.last_optional("user", "index")?;
+                Command::ViewAnalytics { user, index }
+            }
+            'e' => Command::Serve {
+                user: args.next("user")?,
+                index: args.last("index")?,
+            },
+            'h' => Command::GetVotes {
+                user: args.last("user")?,
+            },
+            'z' => {
+                let user = args.next("user")?;
+                let votes = number(command, "votes", args.last("votes")?)?;
+                Command::SetVotes { user, votes }
+            }
+            'i' => Command::RenamePerson {
+                user: args.next("user")?,
+                name: args.next("name")?,
+                new_name: args.last("new name")?,
+            },
+            'x' => Command::RemovePerson {
+                user: args.next("user")?,
+                name: args.last("name")?,
+            },
+            'f' => Command::Deactivate {
+                user: args.next("user")?,
+                name: args.last("name")?,
+            },
+            'w' => Command::Reactivate {
+                user: args.next("user")?,
+                name: args.last("name")?,
+            },
+            c => return Err(ParseError::UnknownCommand(c)),
+        })
+    }
+}
+
+// Encodes the command as a POST body that `Command::parse()` accepts.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::List => write!(f, "l"),
+            Command::Get { index } => write!(f, "g {index}"),
+            Command::Vote { user, index } => write!(f, "v {user}\\{index}"),
+            Command::Unvote { user, index } => write!(f, "u {user}\\{index}"),
+            Command::ViewVotes { user } => write!(f, "a {user}"),
+            Command::NewUser { name, invite: None } => write!(f, "c {name}"),
+            Command::NewUser {
+                name,
+                invite: Some(invite),
+            } => write!(f, "c {name}\\{invite}"),
+            Command::NewDinner { user, name } => {
+                write!(f, "n {user}\\{name}")
+            }
+            Command::EditShortname { user, index, name } => {
+                write!(f, "s {user}\\{index}\\{name}")
+            }
+            Command::EditLongname { user, index, name } => {
+                write!(f, "t {user}\\{index}\\{name}")
+            }
+            Command::EditDetails { user, index, name } => {
+                write!(f, "m {user}\\{index}\\{name}")
+            }
+            Command::DeleteDinner { user, index } => {
+                write!(f, "d {user}\\{index}")
+            }
+            Command::SetRating {
+                user,
+                index,
+                stars,
+                note: None,
+            } => write!(f, "r {user}\\{index}\\{stars}"),
+            Command::SetRating {
+                user,
+                index,
+                stars,
+                note: Some(note),
+            } => write!(f, "r {user}\\{index}\\{stars}\\{note}"),
+            Command::ClearRating { user, index } => {
+                write!(f, "r {user}\\{index}\\0")
+            }
+            Command::ViewAnalytics { user, index: None } => {
+                write!(f, "y {user}")
+            }
+            Command::ViewAnalytics {
+                user,
+                index: Some(index),
+            } => write!(f, "y {user}\\{index}"),
+            Command::Serve { user, index } => write!(f, "e {user}\\{index}"),
+            Command::GetVotes { user } => write!(f, "h {user}"),
+            Command::SetVotes { user, votes } => {
+                write!(f, "z {user}\\{votes}")
+            }
+            Command::RenamePerson {
+                user,
+                name,
+                new_name,
+            } => write!(f, "i {user}\\{name}\\{new_name}"),
+            Command::RemovePerson { user, name } => {
+                write!(f, "x {user}\\{name}")
+            }
+            Command::Deactivate { user, name } => {
+                write!(f, "f {user}\\{name}")
+            }
+            Command::Reactivate { user, name } => {
+                write!(f, "w {user}\\{name}")
+            }
+        }
+    }
+}
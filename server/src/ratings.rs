@@ -0,0 +1,79 @@
+// Per-person ratings of dinners ("r" command).
+
+use serde::{Deserialize, Serialize};
+
+use crate::Dinner;
+
+// Most stars a dinner can get
+pub const MAX_STARS: u8 = 5;
+// Longest note allowed on a rating, in characters
+pub const MAX_NOTE_CHARS: usize = 500;
+
+// A person's rating of a dinner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rating {
+    // Who rated it
+    pub person: String,
+    // 1 to 5 stars
+    pub stars: u8,
+    // What they thought of it
+    pub note: Option<String>,
+}
+
+// What everyone thought of a dinner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RatingSummary {
+    // Number of ratings
+    pub count: usize,
+    // Average stars, if anyone rated it
+    pub mean: Option<f32>,
+    // Number of ratings with 1 star, 2 stars...
+    pub distribution: [usize; MAX_STARS as usize],
+}
+
+impl Rating {
+    // Whether the stars are in range and the note isn't too long.
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_STARS).contains(&self.stars)
+            && self
+                .note
+                .as_ref()
+                .is_none_or(|note| note.chars().count() <= MAX_NOTE_CHARS)
+    }
+}
+
+impl Dinner {
+    // Add or replace a person's rating.
+    pub fn rate(&mut self, rating: Rating) {
+        self.unrate(&rating.person);
+        self.ratings.push(rating);
+    }
+
+    // Remove a person's rating, returning whether they had one.
+    pub fn unrate(&mut self, person: &str) -> bool {
+        let len = self.ratings.len();
+        self.ratings.retain(|rating| rating.person != person);
+        self.ratings.len() != len
+    }
+
+    pub fn rating_summary(&self) -> RatingSummary {
+        let mut distribution = [0; MAX_STARS as usize];
+        let mut total = 0u32;
+        let mut count = 0;
+
+        // Skip anything out of range, in case the file was edited by hand
+        for rating in self.ratings.iter().filter(|r| r.is_valid()) {
+            distribution[usize::from(rating.stars) - 1] += 1;
+            total += u32::from(rating.stars);
+            count += 1;
+        }
+
+        let mean = (count != 0).then(|| total as f32 / count as f32);
+
+        RatingSummary {
+            count,
+            mean,
+            distribution,
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// Roles, and what each one may do.  Every change is checked here before it's
+// applied, so the events themselves only deal with what they change.
+
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    outcome::{Missing, Outcome, Rejection},
+    DatabaseData, DbEvent,
+};
+
+// What someone is in the household (stored as a string, since muon doesn't do
+// enums)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
+pub enum Role {
+    // Runs the server, can do anything.  There's only one.
+    Owner,
+    // Can do anything but change the owner
+    Admin,
+    // Votes, rates and adds dinners
+    Member,
+    // Votes and rates
+    Child,
+    // Only votes, with the votes they were given
+    Guest,
+}
+
+// Something only some roles may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    // Spend votes, and take back their own
+    Vote,
+    // Vote without using up votes
+    VoteForFree,
+    // Take back someone else's vote
+    UnvoteOthers,
+    // Rate dinners and clear their rating
+    Rate,
+    AddDinner,
+    // Rename, describe, change the photo of and delete dinners
+    EditDinners,
+    Serve,
+    // Set everyone's votes
+    SetVotes,
+    // See everyone's votes, not just their own
+    SeeEveryonesVotes,
+    // Set someone else's password
+    SetPasswords,
+    // Back up, restore, export and import the database
+    ManageData,
+    // Change the role of, rename, remove and deactivate people other than
+    // the owner, and invite new ones
+    ManagePeople,
+    // Hand over ownership, and rename the owner
+    TransferOwnership,
+}
+
+use Permission::*;
+
+// Everything but `TransferOwnership`
+const ADMIN: [Permission; 12] = [
+    Vote,
+    VoteForFree,
+    UnvoteOthers,
+    Rate,
+    AddDinner,
+    EditDinners,
+    Serve,
+    SetVotes,
+    SeeEveryonesVotes,
+    SetPasswords,
+    ManageData,
+    ManagePeople,
+];
+
+// Everything
+const OWNER: [Permission; 13] = [
+    Vote,
+    VoteForFree,
+    UnvoteOthers,
+    Rate,
+    AddDinner,
+    EditDinners,
+    Serve,
+    SetVotes,
+    SeeEveryonesVotes,
+    SetPasswords,
+    ManageData,
+    ManagePeople,
+    TransferOwnership,
+];
+
+impl Role {
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Role::Owner => &OWNER,
+            Role::Admin => &ADMIN,
+            Role::Member => &[Vote, Rate, AddDinner],
+            Role::Child => &[Vote, Rate],
+            Role::Guest => &[Vote],
+        }
+    }
+
+    pub fn can(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    // Whether this is shown as an admin to apps from before roles.
+    pub fn is_admin(self) -> bool {
+        matches!(self, Role::Owner | Role::Admin)
+    }
+
+    // The role of someone who's only known to be an admin or not.
+    pub fn from_admin(admin: bool) -> Self {
+        if admin {
+            Role::Admin
+        } else {
+            Role::Member
+        }
+    }
+}
+
+impl Permission {
+    // Why someone without it is refused.
+    fn refusal(self) -> Rejection {
+        match self {
+            Vote | Rate | AddDinner => Rejection::NotAllowed,
+            UnvoteOthers => Rejection::NotYourVote,
+            VoteForFree | EditDinners | Serve | SetVotes
+            | SeeEveryonesVotes | SetPasswords | ManageData | ManagePeople => {
+                Rejection::NotAdmin
+            }
+            TransferOwnership => Rejection::NotOwner,
+        }
+    }
+}
+
+impl From<Role> for String {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Member => "member",
+            Role::Child => "child",
+            Role::Guest => "guest",
+        }
+        .to_string()
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(role: String) -> Result<Self, Self::Error> {
+        match role.as_str() {
+            "owner" => Ok(Role::Owner),
+            "admin" => Ok(Role::Admin),
+            "member" => Ok(Role::Member),
+            "child" => Ok(Role::Child),
+            "guest" => Ok(Role::Guest),
+            _ => Err(format!("unknown role `{role}`")),
+        }
+    }
+}
+
+// Make sure `user` exists and may do `permission`.
+pub(crate) fn check_permission(
+    db: &DatabaseData,
+    user: &str,
+    permission: Permission,
+) -> Result<(), Outcome> {
+    match db.people.get(user) {
+        Some(person) if person.role.can(permission) => Ok(()),
+        Some(_) => Err(Outcome::rejected(permission.refusal())),
+        None => Err(Outcome::not_found(Missing::Person)),
+    }
+}
+
+// Make sure whoever makes `event` exists and may make it.
+pub(crate) fn check_event(
+    db: &DatabaseData,
+    event: &DbEvent,
+) -> Result<(), Outcome> {
+    let Some(user) = event.actor() else {
+        return Ok(());
+    };
+    if db.people.get(user).is_some_and(|person| person.deactivated) {
+        return Err(Outcome::rejected(Rejection::Deactivated));
+    }
+    match needed(db, event) {
+        Some(permission) => check_permission(db, user, permission),
+        None if db.people.contains_key(user) => Ok(()),
+        None => Err(Outcome::not_found(Missing::Person)),
+    }
+}
+
+// The permission needed to make `event`, if any.
+fn needed(db: &DatabaseData, event: &DbEvent) -> Option<Permission> {
+    Some(match event {
+        DbEvent::NewUser { .. } | DbEvent::Setup { .. } => return None,
+        DbEvent::Vote { .. } => Vote,
+        DbEvent::Unvote { user, index } => {
+            let voter =
+                db.dinner(index).and_then(|dinner| dinner.vote.as_ref());
+            match voter {
+                Some(voter) if voter != user => UnvoteOthers,
+                _ => Vote,
+            }
+        }
+        DbEvent::NewDinner { .. } => AddDinner,
+        DbEvent::EditShortname { .. }
+        | DbEvent::EditLongname { .. }
+        | DbEvent::EditDetails { .. }
+        | DbEvent::EditDinner { .. }
+        | DbEvent::EditPhoto { .. }
+        | DbEvent::DeleteDinner { .. } => EditDinners,
+        DbEvent::SetRating { .. } | DbEvent::ClearRating { .. } => Rate,
+        DbEvent::Serve { .. } => Serve,
+        DbEvent::SetVotes { .. } => SetVotes,
+        // Or they could log in as the owner and take over
+        DbEvent::SetPassword { user, name, .. } if user != name => {
+            unless_owner(db, name, SetPasswords)
+        }
+        DbEvent::SetPassword { .. } => return None,
+        DbEvent::SetRole {
+            role: Role::Owner, ..
+        } => TransferOwnership,
+        DbEvent::NewInvite { .. } | DbEvent::DeleteInvite { .. } => {
+            ManagePeople
+        }
+        // Everyone can rename or remove themselves
+        DbEvent::RenamePerson { user, name, .. }
+        | DbEvent::RemovePerson { user, name }
+            if user == name =>
+        {
+            return None
+        }
+        DbEvent::SetRole { name, .. }
+        | DbEvent::RenamePerson { name, .. }
+        | DbEvent::RemovePerson { name, .. }
+        | DbEvent::Deactivate { name, .. }
+        | DbEvent::Reactivate { name, .. } => {
+            unless_owner(db, name, ManagePeople)
+        }
+    })
+}
+
+// `permission` for changing `name`, unless they're the owner, who only the
+// owner can change.
+fn unless_owner(
+    db: &DatabaseData,
+    name: &str,
+    permission: Permission,
+) -> Permission {
+    match db.people.get(name) {
+        Some(person) if person.role == Role::Owner => TransferOwnership,
+        _ => permission,
+    }
+}
+
+// How many owners and admins there are, not counting deactivated ones.
+pub(crate) fn admins(db: &DatabaseData) -> usize {
+    db.people
+        .values()
+        .filter(|person| person.role.is_admin() && !person.deactivated)
+        .count()
+}
@@ -0,0 +1,591 @@
+// SQLite storage, with a table for each kind of thing so a change only
+// touches its own rows.
+
+use std::{collections::HashMap, convert::TryFrom, io, path::PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::{
+    dinner_named,
+    history::{HistoryEvent, HistoryKind},
+    invites::Invite,
+    journal::Journal,
+    ratings::Rating,
+    roles::Role,
+    storage::Storage,
+    DatabaseData, DbEvent, Dinner, DinnerId, Person,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS people (
+        name TEXT PRIMARY KEY,
+        votes INTEGER NOT NULL,
+        admin INTEGER NOT NULL,
+        password TEXT,
+        role TEXT,
+        deactivated INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS dinners (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        short TEXT NOT NULL,
+        long TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS votes (
+        dinner INTEGER PRIMARY KEY
+            REFERENCES dinners (id) ON DELETE CASCADE,
+        person TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS votes_person ON votes (person);
+    CREATE TABLE IF NOT EXISTS ratings (
+        dinner INTEGER NOT NULL REFERENCES dinners (id) ON DELETE CASCADE,
+        person TEXT NOT NULL,
+        stars INTEGER NOT NULL,
+        note TEXT,
+        PRIMARY KEY (dinner, person)
+    );
+    CREATE INDEX IF NOT EXISTS ratings_person ON ratings (person);
+    CREATE TABLE IF NOT EXISTS photos (
+        dinner INTEGER PRIMARY KEY REFERENCES dinners (id) ON DELETE CASCADE,
+        photo BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        person TEXT NOT NULL,
+        dinner INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_dinner ON history (dinner, at);
+    CREATE INDEX IF NOT EXISTS history_person ON history (person, at);
+    CREATE TABLE IF NOT EXISTS invites (
+        code TEXT PRIMARY KEY,
+        role TEXT NOT NULL,
+        votes INTEGER NOT NULL,
+        uses INTEGER NOT NULL,
+        expires INTEGER,
+        by TEXT NOT NULL
+    );
+";
+
+// Columns added since the table was first made, as (table, column, type)
+const ADDED_COLUMNS: [(&str, &str, &str); 3] = [
+    ("people", "password", "TEXT"),
+    ("people", "role", "TEXT"),
+    ("people", "deactivated", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+// Add any of `ADDED_COLUMNS` missing from a file made by an older version.
+fn add_columns(connection: &Connection) -> rusqlite::Result<()> {
+    for (table, column, kind) in ADDED_COLUMNS {
+        let exists: bool = connection.query_row(
+            "SELECT EXISTS (
+                SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2
+            )",
+            [table, column],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            connection.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {kind};"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+// The database in an SQLite file
+pub(crate) struct Sqlite {
+    connection: Connection,
+    // Muon database to import if nothing's been saved yet
+    import: Journal,
+}
+
+impl Sqlite {
+    pub(crate) fn open(path: PathBuf, import: Journal) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(error)?;
+        connection
+            .execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(error)?;
+        connection.execute_batch(SCHEMA).map_err(error)?;
+        add_columns(&connection).map_err(error)?;
+
+        Ok(Self { connection, import })
+    }
+
+    fn read(&self) -> rusqlite::Result<Option<DatabaseData>> {
+        let db = &self.connection;
+        // Only set once something's been saved
+        let next_dinner: Option<DinnerId> = db
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'next_dinner'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(next_dinner) = next_dinner else {
+            return Ok(None);
+        };
+
+        let mut dinners = HashMap::new();
+        let mut query =
+            db.prepare("SELECT id, name, short, long FROM dinners")?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let dinner = Dinner {
+                short: row.get(2)?,
+                long: row.get(3)?,
+                photo: None,
+                vote: None,
+                ratings: Vec::new(),
+                name: row.get(1)?,
+            };
+            dinners.insert(row.get(0)?, dinner);
+        }
+
+        let mut query = db.prepare("SELECT dinner, person FROM votes")?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(dinner) = dinners.get_mut(&row.get(0)?) {
+                dinner.vote = Some(row.get(1)?);
+            }
+        }
+
+        let mut query = db.prepare("SELECT dinner, photo FROM photos")?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(dinner) = dinners.get_mut(&row.get(0)?) {
+                dinner.photo = Some(row.get::<_, Vec<u8>>(1)?.into());
+            }
+        }
+
+        let mut query = db.prepare(
+            "SELECT dinner, person, stars, note FROM ratings ORDER BY rowid",
+        )?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(dinner) = dinners.get_mut(&row.get(0)?) {
+                dinner.ratings.push(Rating {
+                    person: row.get(1)?,
+                    stars: row.get(2)?,
+                    note: row.get(3)?,
+                });
+            }
+        }
+
+        let mut people = HashMap::new();
+        let mut query = db.prepare(
+            "SELECT name, votes, admin, password, role, deactivated FROM people",
+        )?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            // Rows from before roles only have `admin`
+            let role: Option<String> = row.get(4)?;
+            let role = match role.map(Role::try_from) {
+                Some(Ok(role)) => role,
+                _ => Role::from_admin(row.get(2)?),
+            };
+            let person = Person {
+                votes: row.get(1)?,
+                role,
+                password: row.get(3)?,
+                deactivated: row.get(5)?,
+            };
+            people.insert(row.get(0)?, person);
+        }
+
+        let mut history = Vec::new();
+        let mut query = db.prepare(
+            "SELECT person, dinner, kind, at FROM history ORDER BY rowid",
+        )?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(2)?;
+            let Ok(kind) = HistoryKind::try_from(kind) else {
+                continue;
+            };
+            history.push(HistoryEvent {
+                person: row.get(0)?,
+                dinner: row.get(1)?,
+                kind,
+                at: row.get(3)?,
+            });
+        }
+
+        let mut invites = HashMap::new();
+        let mut query = db.prepare(
+            "SELECT code, role, votes, uses, expires, by FROM invites",
+        )?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let role: String = row.get(1)?;
+            let Ok(role) = Role::try_from(role) else {
+                continue;
+            };
+            let invite = Invite {
+                role,
+                votes: row.get(2)?,
+                uses: row.get(3)?,
+                expires: row.get(4)?,
+                by: row.get(5)?,
+            };
+            invites.insert(row.get(0)?, invite);
+        }
+
+        Ok(Some(DatabaseData {
+            dinners,
+            next_dinner,
+            people,
+            history,
+            invites,
+        }))
+    }
+}
+
+impl Storage for Sqlite {
+    fn load(&mut self) -> io::Result<Option<DatabaseData>> {
+        if let Some(data) = self.read().map_err(error)? {
+            return Ok(Some(data));
+        }
+
+        // Nothing saved yet, so bring over the muon file if there is one
+        let Some(data) = self.import.load()? else {
+            return Ok(None);
+        };
+        self.snapshot(&data)?;
+        tide::log::info!("Imported the muon database");
+        Ok(Some(data))
+    }
+
+    fn apply(
+        &mut self,
+        event: &DbEvent,
+        _: u64,
+        data: &DatabaseData,
+    ) -> io::Result<()> {
+        let tx = self.connection.transaction().map_err(error)?;
+        write_event(&tx, event, data).map_err(error)?;
+        tx.commit().map_err(error)
+    }
+
+    fn snapshot(&mut self, data: &DatabaseData) -> io::Result<()> {
+        let tx = self.connection.transaction().map_err(error)?;
+        write_all(&tx, data).map_err(error)?;
+        tx.commit().map_err(error)
+    }
+}
+
+// Save the rows `event` changed.
+fn write_event(
+    tx: &Transaction<'_>,
+    event: &DbEvent,
+    data: &DatabaseData,
+) -> rusqlite::Result<()> {
+    match event {
+        DbEvent::NewUser { name, invite, .. } => {
+            write_person(tx, data, name)?;
+            if invite.is_some() {
+                write_invites(tx, data)?;
+            }
+        }
+        DbEvent::SetPassword { name, .. } | DbEvent::Setup { name, .. } => {
+            write_person(tx, data, name)?
+        }
+        DbEvent::Vote { user, index } | DbEvent::Unvote { user, index } => {
+            write_person(tx, data, user)?;
+            if let Some(id) = find_dinner(tx, index)? {
+                write_vote(tx, data, id)?;
+            }
+        }
+        DbEvent::Serve { index, .. } => {
+            if let Some(id) = find_dinner(tx, index)? {
+                write_vote(tx, data, id)?;
+            }
+        }
+        DbEvent::NewDinner { name, .. } => {
+            if let Some(id) = dinner_named(&data.dinners, name) {
+                write_dinner(tx, data, id)?;
+            }
+        }
+        DbEvent::EditShortname { index, .. }
+        | DbEvent::EditLongname { index, .. }
+        | DbEvent::EditDetails { index, .. }
+        | DbEvent::EditDinner { index, .. } => {
+            if let Some(id) = find_dinner(tx, index)? {
+                write_dinner(tx, data, id)?;
+            }
+        }
+        DbEvent::EditPhoto { index, .. } => {
+            if let Some(id) = find_dinner(tx, index)? {
+                write_photo(tx, data, id)?;
+            }
+        }
+        DbEvent::DeleteDinner { index, .. } => {
+            if let Some(id) = find_dinner(tx, index)? {
+                tx.execute("DELETE FROM dinners WHERE id = ?1", [id])?;
+            }
+        }
+        DbEvent::SetRating { user, index, .. }
+        | DbEvent::ClearRating { user, index } => {
+            if let Some(id) = find_dinner(tx, index)? {
+                write_rating(tx, data, id, user)?;
+            }
+        }
+        // Handing over ownership changes the old owner too
+        DbEvent::SetVotes { .. } | DbEvent::SetRole { .. } => {
+            for name in data.people.keys() {
+                write_person(tx, data, name)?;
+            }
+        }
+        DbEvent::RenamePerson { name, new_name, .. } => {
+            for table in ["votes", "ratings", "history"] {
+                tx.execute(
+                    &format!(
+                        "UPDATE {table} SET person = ?2 WHERE person = ?1"
+                    ),
+                    [name, new_name],
+                )?;
+            }
+            write_person(tx, data, name)?;
+            write_person(tx, data, new_name)?;
+            write_invites(tx, data)?;
+        }
+        DbEvent::RemovePerson { name, .. } => {
+            for table in ["votes", "ratings"] {
+                tx.execute(
+                    &format!("DELETE FROM {table} WHERE person = ?1"),
+                    [name],
+                )?;
+            }
+            write_person(tx, data, name)?;
+        }
+        DbEvent::Deactivate { name, .. } => {
+            tx.execute("DELETE FROM votes WHERE person = ?1", [name])?;
+            write_person(tx, data, name)?;
+        }
+        DbEvent::Reactivate { name, .. } => write_person(tx, data, name)?,
+        DbEvent::NewInvite { .. } | DbEvent::DeleteInvite { .. } => {
+            write_invites(tx, data)?
+        }
+    }
+
+    write_history(tx, data)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('next_dinner', ?1)",
+        [data.next_dinner],
+    )?;
+    Ok(())
+}
+
+// Replace everything with `data`.
+fn write_all(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DELETE FROM dinners; DELETE FROM people; DELETE FROM history;",
+    )?;
+    for (id, dinner) in &data.dinners {
+        write_dinner(tx, data, *id)?;
+        write_vote(tx, data, *id)?;
+        write_photo(tx, data, *id)?;
+        for rating in &dinner.ratings {
+            write_rating(tx, data, *id, &rating.person)?;
+        }
+    }
+    for name in data.people.keys() {
+        write_person(tx, data, name)?;
+    }
+    write_invites(tx, data)?;
+
+    write_history(tx, data)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('next_dinner', ?1)",
+        [data.next_dinner],
+    )?;
+    Ok(())
+}
+
+// Find a dinner as it was before the change, by ID or name (like
+// `DatabaseData::find_dinner()`).
+fn find_dinner(
+    tx: &Transaction<'_>,
+    index: &str,
+) -> rusqlite::Result<Option<DinnerId>> {
+    if let Ok(id) = index.parse::<DinnerId>() {
+        let found = tx
+            .query_row("SELECT id FROM dinners WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        if found.is_some() {
+            return Ok(found);
+        }
+    }
+
+    tx.query_row("SELECT id FROM dinners WHERE name = ?1", [index], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+fn write_person(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+    name: &str,
+) -> rusqlite::Result<()> {
+    let Some(person) = data.people.get(name) else {
+        tx.execute("DELETE FROM people WHERE name = ?1", [name])?;
+        return Ok(());
+    };
+    tx.execute(
+        "INSERT OR REPLACE INTO people
+            (name, votes, admin, password, role, deactivated)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            name,
+            person.votes,
+            person.role.is_admin(),
+            person.password,
+            String::from(person.role),
+            person.deactivated,
+        ],
+    )?;
+    Ok(())
+}
+
+// Replace the invites, there are only ever a few.
+fn write_invites(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM invites", [])?;
+    for (code, invite) in &data.invites {
+        tx.execute(
+            "INSERT INTO invites (code, role, votes, uses, expires, by)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                code,
+                String::from(invite.role),
+                invite.votes,
+                invite.uses,
+                invite.expires,
+                invite.by,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_dinner(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+    id: DinnerId,
+) -> rusqlite::Result<()> {
+    let Some(dinner) = data.dinners.get(&id) else {
+        tx.execute("DELETE FROM dinners WHERE id = ?1", [id])?;
+        return Ok(());
+    };
+    // Not `INSERT OR REPLACE`, which would delete the votes, photo and
+    // ratings with the old row
+    tx.execute(
+        "INSERT INTO dinners (id, name, short, long) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                short = excluded.short,
+                long = excluded.long",
+        params![id, dinner.name, dinner.short, dinner.long],
+    )?;
+    Ok(())
+}
+
+fn write_vote(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+    id: DinnerId,
+) -> rusqlite::Result<()> {
+    // Whoever's vote it was may have had it given back
+    let voter: Option<String> = tx
+        .query_row("SELECT person FROM votes WHERE dinner = ?1", [id], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    tx.execute("DELETE FROM votes WHERE dinner = ?1", [id])?;
+    if let Some(person) = data.dinners.get(&id).and_then(|d| d.vote.as_ref()) {
+        tx.execute(
+            "INSERT INTO votes (dinner, person) VALUES (?1, ?2)",
+            params![id, person],
+        )?;
+    }
+    if let Some(voter) = voter {
+        write_person(tx, data, &voter)?;
+    }
+    Ok(())
+}
+
+fn write_photo(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+    id: DinnerId,
+) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM photos WHERE dinner = ?1", [id])?;
+    if let Some(photo) = data.dinners.get(&id).and_then(|d| d.photo.as_ref()) {
+        tx.execute(
+            "INSERT INTO photos (dinner, photo) VALUES (?1, ?2)",
+            params![id, &photo[..]],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_rating(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+    id: DinnerId,
+    person: &str,
+) -> rusqlite::Result<()> {
+    tx.execute(
+        "DELETE FROM ratings WHERE dinner = ?1 AND person = ?2",
+        params![id, person],
+    )?;
+    let rating = data.dinners.get(&id).and_then(|dinner| {
+        dinner.ratings.iter().find(|rating| rating.person == person)
+    });
+    if let Some(rating) = rating {
+        tx.execute(
+            "INSERT INTO ratings (dinner, person, stars, note)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![id, person, rating.stars, rating.note],
+        )?;
+    }
+    Ok(())
+}
+
+// Add the history events that aren't saved yet (history is only appended).
+fn write_history(
+    tx: &Transaction<'_>,
+    data: &DatabaseData,
+) -> rusqlite::Result<()> {
+    let saved: usize =
+        tx.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))?;
+    for event in data.history.iter().skip(saved) {
+        tx.execute(
+            "INSERT INTO history (person, dinner, kind, at)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![
+                event.person,
+                event.dinner,
+                String::from(event.kind),
+                event.at
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,280 @@
+// Where the database is kept between runs.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    history, journal::Journal, migrations, sqlite::Sqlite, DatabaseData,
+    DatabaseDataSerde, DbEvent,
+};
+
+// A way of saving the database
+pub trait Storage: Send {
+    // Read the saved database, `None` if nothing's been saved yet.
+    fn load(&mut self) -> io::Result<Option<DatabaseData>>;
+
+    // Save a change, `data` is the database after `event` was applied at
+    // `at` (seconds since the Unix epoch).
+    fn apply(
+        &mut self,
+        event: &DbEvent,
+        at: u64,
+        data: &DatabaseData,
+    ) -> io::Result<()>;
+
+    // Save the whole database.
+    fn snapshot(&mut self, data: &DatabaseData) -> io::Result<()>;
+}
+
+// Which storage to use, picked at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    // A journal of changes, compacted into a muon file now and then
+    Journal,
+    // A muon file, rewritten on every change
+    Muon,
+    // An SQLite file, imported from the muon database the first time
+    Sqlite,
+    // Nothing saved, for tests
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "journal" => Ok(Backend::Journal),
+            "muon" => Ok(Backend::Muon),
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("unknown storage backend `{name}`")),
+        }
+    }
+}
+
+impl Backend {
+    // Open the storage, kept in `dir` if it uses files.
+    pub fn open(self, dir: &Path) -> io::Result<Box<dyn Storage>> {
+        Ok(match self {
+            Backend::Journal => Box::new(Journal::new(
+                dir.join("database"),
+                dir.join("journal"),
+            )),
+            Backend::Muon => Box::new(MuonFile::new(dir.join("database"))),
+            Backend::Sqlite => Box::new(Sqlite::open(
+                dir.join("database.sqlite3"),
+                Journal::new(dir.join("database"), dir.join("journal")),
+            )?),
+            Backend::Memory => Box::<Memory>::default(),
+        })
+    }
+}
+
+// The whole database in one muon file
+pub(crate) struct MuonFile {
+    path: PathBuf,
+}
+
+impl MuonFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    // The file as of the write before the last one
+    fn backup(&self) -> PathBuf {
+        self.path.with_extension("bak")
+    }
+
+    // Read the file as it's stored, migrated to the current version, `None`
+    // if it doesn't exist.
+    pub(crate) fn read(&self) -> io::Result<Option<DatabaseDataSerde>> {
+        let Some(mut data) = self.read_or_backup()? else {
+            return Ok(None);
+        };
+
+        let changes = migrations::migrate(&mut data)?;
+        if !changes.is_empty() {
+            tide::log::info!("Migrated {}:", self.path.display());
+            for change in changes {
+                tide::log::info!("- {}", change);
+            }
+            // The old version is kept as the backup
+            self.write(&data)?;
+        }
+
+        Ok(Some(data))
+    }
+
+    // Read the file, `None` if it doesn't exist.  If it's corrupt, it's moved
+    // out of the way and the backup is used instead.
+    fn read_or_backup(&self) -> io::Result<Option<DatabaseDataSerde>> {
+        let error = match read_muon(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => e,
+            result => return result,
+        };
+
+        let quarantine = self
+            .path
+            .with_extension(format!("corrupt-{}", history::now()));
+        std::fs::rename(&self.path, &quarantine)?;
+        tide::log::error!(
+            "{} is corrupt ({}), moved it to {}",
+            self.path.display(),
+            error,
+            quarantine.display(),
+        );
+
+        let backup = self.backup();
+        let data = match read_muon(&backup) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is corrupt and there's no backup",
+                        self.path.display()
+                    ),
+                ))
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is corrupt and so is the backup ({e})",
+                        self.path.display()
+                    ),
+                ))
+            }
+        };
+        let saved = std::fs::metadata(&backup)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        tide::log::warn!(
+            "Using the backup from {} (seconds since the Unix epoch), \
+                changes saved after that are lost",
+            saved,
+        );
+
+        // Put it back, so there's a good file if we stop before a change
+        std::fs::copy(&backup, &self.path)?;
+        sync_dir(&self.path)?;
+        Ok(Some(data))
+    }
+
+    // Replace the file, so that it's either all there or not written at all
+    // if we crash.
+    pub(crate) fn write(&self, data: &DatabaseDataSerde) -> io::Result<()> {
+        let temp = write_temp(&self.path, data)?;
+
+        // Keep the old file as the backup
+        if self.path.exists() {
+            let backup = self.backup();
+            let _ = std::fs::remove_file(&backup);
+            if std::fs::hard_link(&self.path, &backup).is_err() {
+                std::fs::copy(&self.path, &backup)?;
+            }
+        }
+
+        // Move the temp file onto the old file
+        std::fs::rename(&temp, &self.path)?;
+        sync_dir(&self.path)
+    }
+}
+
+// Write `data` to a temp file next to `path` and sync it, returning the temp
+// file.
+pub(crate) fn write_temp(
+    path: &Path,
+    data: &DatabaseDataSerde,
+) -> io::Result<PathBuf> {
+    let encoded = muon_rs::to_vec(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let temp = path.with_extension("temp");
+
+    let mut file = File::create(&temp)?;
+    file.write_all(&encoded)?;
+    file.sync_all()?;
+    Ok(temp)
+}
+
+// Read a muon file, `None` if it doesn't exist.
+pub(crate) fn read_muon(path: &Path) -> io::Result<Option<DatabaseDataSerde>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    muon_rs::from_slice(&bytes)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Make sure the directory entries of the files in `path`'s directory are on
+// disk, for renames and new files.
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+impl Storage for MuonFile {
+    fn load(&mut self) -> io::Result<Option<DatabaseData>> {
+        Ok(self.read()?.map(DatabaseData::from_serde))
+    }
+
+    fn apply(
+        &mut self,
+        _: &DbEvent,
+        _: u64,
+        data: &DatabaseData,
+    ) -> io::Result<()> {
+        self.snapshot(data)
+    }
+
+    fn snapshot(&mut self, data: &DatabaseData) -> io::Result<()> {
+        self.write(&data.to_serde())
+    }
+}
+
+// Keeps the last snapshot in memory only
+#[derive(Default)]
+pub struct Memory {
+    data: Option<DatabaseData>,
+}
+
+impl Storage for Memory {
+    fn load(&mut self) -> io::Result<Option<DatabaseData>> {
+        Ok(self.data.clone())
+    }
+
+    fn apply(
+        &mut self,
+        _: &DbEvent,
+        _: u64,
+        data: &DatabaseData,
+    ) -> io::Result<()> {
+        self.snapshot(data)
+    }
+
+    fn snapshot(&mut self, data: &DatabaseData) -> io::Result<()> {
+        self.data = Some(data.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+// Import and export of dinners, people, votes and ratings as JSON or CSV.
+
+use std::{collections::HashSet, sync::Arc};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    dinner_named, photos,
+    ratings::Rating,
+    roles::{self, check_permission, Permission, Role},
+    valid_name, DatabaseData, Dinner, Person,
+};
+
+// File format
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+// What to do with photos when exporting
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Photos {
+    // Leave them out, so importing keeps the photos there are
+    #[default]
+    Omit,
+    // Include them, base64 encoded
+    Base64,
+}
+
+// How to import
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    // Add and update, leaving everything else alone
+    #[default]
+    Merge,
+    // Remove the dinners and people that aren't in the import
+    Replace,
+}
+
+// The whole database, as exported
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Export {
+    #[serde(default)]
+    pub(crate) dinners: Vec<DinnerExport>,
+    #[serde(default)]
+    pub(crate) people: Vec<PersonExport>,
+}
+
+// A dinner, matched by name when importing.  Fields that are left out are
+// left alone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DinnerExport {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) short: Option<String>,
+    #[serde(default)]
+    pub(crate) long: Option<String>,
+    // Who voted for it, `null` for nobody
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) vote: Option<Option<String>>,
+    // Base64 encoded photo, `null` for no photo
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) photo: Option<Option<String>>,
+    #[serde(default)]
+    pub(crate) ratings: Vec<Rating>,
+}
+
+// A person, matched by name when importing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PersonExport {
+    pub(crate) name: String,
+    // Left as they are if left out
+    #[serde(default)]
+    pub(crate) votes: Option<u16>,
+    // Only read if there's no `role`, for files from before roles
+    #[serde(default)]
+    pub(crate) admin: bool,
+    #[serde(default)]
+    pub(crate) role: Option<Role>,
+}
+
+// One line of a CSV file, `kind` says which columns are used:
+//  - `dinner` (the default): `dinner`, `short`, `long`, `vote`, `photo`
+//  - `person`: `person`, `votes`, `admin`, `role`
+//  - `rating`: `dinner`, `person`, `stars`, `note`
+// Empty cells are left alone when importing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct Row {
+    kind: Option<String>,
+    dinner: Option<String>,
+    short: Option<String>,
+    long: Option<String>,
+    vote: Option<String>,
+    person: Option<String>,
+    votes: Option<u16>,
+    admin: Option<bool>,
+    role: Option<Role>,
+    stars: Option<u8>,
+    note: Option<String>,
+    photo: Option<String>,
+}
+
+// Tell a `null` apart from a missing field.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl DatabaseData {
+    pub(crate) fn export(&self, photos: Photos) -> Export {
+        let mut dinners: Vec<DinnerExport> = self
+            .dinners
+            .values()
+            .map(|dinner| DinnerExport {
+                name: dinner.name.clone(),
+                short: Some(dinner.short.clone()),
+                long: Some(dinner.long.clone()),
+                vote: Some(dinner.vote.clone()),
+                photo: match photos {
+                    Photos::Omit => None,
+                    Photos::Base64 => {
+                        Some(dinner.photo.as_ref().map(base64::encode))
+                    }
+                },
+                ratings: dinner.ratings.clone(),
+            })
+            .collect();
+        dinners.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut people: Vec<PersonExport> = self
+            .people
+            .iter()
+            .map(|(name, person)| PersonExport {
+                name: name.clone(),
+                votes: Some(person.votes),
+                admin: person.role.is_admin(),
+                role: Some(person.role),
+            })
+            .collect();
+        people.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Export { dinners, people }
+    }
+
+    // The database with `import` applied by `user`, or what's wrong with it.
+    pub(crate) fn import(
+        &self,
+        import: Export,
+        mode: Mode,
+        user: &str,
+    ) -> Result<DatabaseData, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut data = self.clone();
+
+        let mut names = HashSet::new();
+        for person in &import.people {
+            if !valid_name(&person.name) {
+                errors.push(format!(
+                    "person name `{}` is blank or has a backslash or control \
+                        character",
+                    person.name,
+                ));
+            } else if !names.insert(person.name.as_str()) {
+                errors.push(format!("person `{}` is there twice", person.name));
+            }
+        }
+        let mut dinner_names = HashSet::new();
+        for dinner in &import.dinners {
+            if !valid_name(&dinner.name) {
+                errors.push(format!(
+                    "dinner name `{}` is blank or has a backslash or control \
+                        character",
+                    dinner.name,
+                ));
+            } else if !dinner_names.insert(dinner.name.as_str()) {
+                errors.push(format!("dinner `{}` is there twice", dinner.name));
+            }
+        }
+
+        if mode == Mode::Replace {
+            for (name, person) in &self.people {
+                if person.role == Role::Owner && !names.contains(name.as_str())
+                {
+                    errors.push(format!(
+                        "`{name}` is the owner, so can't be left out"
+                    ));
+                }
+            }
+            data.people.retain(|name, _| names.contains(name.as_str()));
+            data.dinners.retain(|_, dinner| {
+                dinner_names.contains(dinner.name.as_str())
+            });
+        }
+
+        for person in &import.people {
+            // Exports leave out passwords and who's deactivated, so keep
+            // what's set here
+            let existing = data.people.get(&person.name);
+            let password =
+                existing.and_then(|existing| existing.password.clone());
+            let deactivated =
+                existing.is_some_and(|existing| existing.deactivated);
+            let votes = person
+                .votes
+                .or(existing.map(|existing| existing.votes))
+                .unwrap_or(0);
+            // A file without roles only says who's an admin, so an owner
+            // stays one
+            let role = person.role.unwrap_or(match existing {
+                Some(existing) if existing.role.is_admin() == person.admin => {
+                    existing.role
+                }
+                _ => Role::from_admin(person.admin),
+            });
+            // Only the changes `SetRole` would allow, and nobody becomes the
+            // owner without it being handed over
+            let current = existing.map(|existing| existing.role);
+            if current != Some(role) {
+                if role == Role::Owner {
+                    errors.push(format!(
+                        "`{}` can't be made the owner by importing",
+                        person.name,
+                    ));
+                } else if current == Some(Role::Owner) {
+                    errors.push(format!(
+                        "`{}` is the owner, so their role can't change",
+                        person.name,
+                    ));
+                } else if check_permission(self, user, Permission::ManagePeople)
+                    .is_err()
+                {
+                    errors.push(format!(
+                        "`{user}` can't change `{}`'s role",
+                        person.name,
+                    ));
+                }
+            }
+            data.people.insert(
+                person.name.clone(),
+                Person {
+                    votes,
+                    role,
+                    password,
+                    deactivated,
+                },
+            );
+        }
+
+        for import in import.dinners {
+            let id = match dinner_named(&data.dinners, &import.name) {
+                Some(id) => id,
+                None => {
+                    let id = data.next_dinner;
+                    data.next_dinner += 1;
+                    data.dinners.insert(
+                        id,
+                        Dinner {
+                            short: "-".to_string(),
+                            long: "-".to_string(),
+                            photo: None,
+                            vote: None,
+                            ratings: Vec::new(),
+                            name: import.name.clone(),
+                        },
+                    );
+                    id
+                }
+            };
+            let dinner = data.dinners.get_mut(&id).unwrap();
+
+            if let Some(short) = import.short {
+                dinner.short = short;
+            }
+            if let Some(long) = import.long {
+                dinner.long = long;
+            }
+            if let Some(vote) = import.vote {
+                if let Some(voter) = &vote {
+                    if !data.people.contains_key(voter) {
+                        errors.push(format!(
+                            "`{voter}` voted for `{}`, but doesn't exist",
+                            import.name,
+                        ));
+                    }
+                }
+                dinner.vote = vote;
+            }
+            if let Some(photo) = import.photo {
+                match photo.map(|photo| decode_photo(&photo)).transpose() {
+                    Ok(photo) => dinner.photo = photo.map(Arc::from),
+                    Err(e) => {
+                        errors.push(format!("photo of `{}` {e}", import.name))
+                    }
+                }
+            }
+
+            if mode == Mode::Replace {
+                dinner.ratings.clear();
+            }
+            for rating in import.ratings {
+                if !rating.is_valid() {
+                    errors.push(format!(
+                        "rating of `{}` by `{}` has stars out of range or \
+                            a note that's too long",
+                        import.name, rating.person,
+                    ));
+                }
+                if !data.people.contains_key(&rating.person) {
+                    errors.push(format!(
+                        "`{}` rated `{}`, but doesn't exist",
+                        rating.person, import.name,
+                    ));
+                }
+                dinner.rate(rating);
+            }
+        }
+
+        // Votes and ratings of people a replace removed
+        let people = &data.people;
+        for dinner in data.dinners.values_mut() {
+            if dinner
+                .vote
+                .as_ref()
+                .is_some_and(|voter| !people.contains_key(voter))
+            {
+                dinner.vote = None;
+            }
+            dinner
+                .ratings
+                .retain(|rating| people.contains_key(&rating.person));
+        }
+
+        if roles::admins(&data) == 0 && roles::admins(self) != 0 {
+            errors.push("there'd be no owner or admin left".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(data)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Decode and check a base64 photo.
+fn decode_photo(photo: &str) -> Result<Vec<u8>, &'static str> {
+    let photo = base64::decode(photo).map_err(|_| "isn't valid base64")?;
+    if photo.len() > photos::MAX_PHOTO_BYTES {
+        return Err("is too big");
+    }
+    if photos::sniff(&photo).is_none() {
+        return Err("isn't a JPEG, PNG, GIF or WebP image");
+    }
+
+    Ok(photo)
+}
+
+impl Export {
+    pub(crate) fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self)
+    }
+
+    pub(crate) fn from_json(json: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(json).map_err(|e| e.to_string())
+    }
+
+    pub(crate) fn to_csv(&self) -> csv::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+
+        for person in &self.people {
+            writer.serialize(Row {
+                kind: Some("person".to_string()),
+                person: Some(person.name.clone()),
+                votes: person.votes,
+                admin: Some(person.admin),
+                role: person.role,
+                ..Row::default()
+            })?;
+        }
+        for dinner in &self.dinners {
+            writer.serialize(Row {
+                kind: Some("dinner".to_string()),
+                dinner: Some(dinner.name.clone()),
+                short: dinner.short.clone(),
+                long: dinner.long.clone(),
+                vote: dinner.vote.clone().flatten(),
+                photo: dinner.photo.clone().flatten(),
+                ..Row::default()
+            })?;
+            for rating in &dinner.ratings {
+                writer.serialize(Row {
+                    kind: Some("rating".to_string()),
+                    dinner: Some(dinner.name.clone()),
+                    person: Some(rating.person.clone()),
+                    stars: Some(rating.stars),
+                    note: rating.note.clone(),
+                    ..Row::default()
+                })?;
+            }
+        }
+
+        writer.into_inner().map_err(|e| e.into_error().into())
+    }
+
+    pub(crate) fn from_csv(csv: &[u8]) -> Result<Self, String> {
+        let mut import = Export::default();
+        let mut reader = csv::Reader::from_reader(csv);
+
+        for (line, row) in (2..).zip(reader.deserialize::<Row>()) {
+            let row = row.map_err(|e| e.to_string())?;
+            let missing = |column| format!("line {line} has no {column}");
+
+            match row.kind.as_deref().unwrap_or("dinner") {
+                "dinner" => {
+                    let name = row.dinner.ok_or_else(|| missing("dinner"))?;
+                    let dinner = import.dinner(name);
+                    dinner.short = row.short.or(dinner.short.take());
+                    dinner.long = row.long.or(dinner.long.take());
+                    if let Some(vote) = row.vote {
+                        dinner.vote = Some(Some(vote));
+                    }
+                    if let Some(photo) = row.photo {
+                        dinner.photo = Some(Some(photo));
+                    }
+                }
+                "person" => import.people.push(PersonExport {
+                    name: row.person.ok_or_else(|| missing("person"))?,
+                    votes: row.votes,
+                    admin: row.admin.unwrap_or(false),
+                    role: row.role,
+                }),
+                "rating" => {
+                    let name = row.dinner.ok_or_else(|| missing("dinner"))?;
+                    let rating = Rating {
+                        person: row.person.ok_or_else(|| missing("person"))?,
+                        stars: row.stars.ok_or_else(|| missing("stars"))?,
+                        note: row.note,
+                    };
+                    import.dinner(name).ratings.push(rating);
+                }
+                kind => {
+                    return Err(format!(
+                        "line {line} has unknown kind `{kind}`"
+                    ))
+                }
+            }
+        }
+
+        Ok(import)
+    }
+
+    // The dinner with this name, added if it isn't there yet.
+    fn dinner(&mut self, name: String) -> &mut DinnerExport {
+        let index = match self.dinners.iter().position(|d| d.name == name) {
+            Some(index) => index,
+            None => {
+                self.dinners.push(DinnerExport {
+                    name,
+                    ..DinnerExport::default()
+                });
+                self.dinners.len() - 1
+            }
+        };
+
+        &mut self.dinners[index]
+    }
+}
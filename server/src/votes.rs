@@ -0,0 +1,70 @@
+// Who voted for what ("a" command).
+
+use serde::Serialize;
+
+use crate::{roles::Permission, DatabaseData};
+
+// The votes of one person
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PersonVotes {
+    pub(crate) name: String,
+    // Votes left to spend
+    pub(crate) votes: u16,
+    // Names of the dinners this person voted for
+    pub(crate) dinners: Vec<String>,
+}
+
+// The votes of the person asking, and of everyone if they may see them
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VoteSummary {
+    #[serde(flatten)]
+    pub(crate) mine: PersonVotes,
+    // Everyone's votes, sorted by name (only for admins)
+    pub(crate) everyone: Option<Vec<PersonVotes>>,
+}
+
+impl DatabaseData {
+    // Get the votes of one person, `None` if they don't exist.
+    fn person_votes(&self, name: &str) -> Option<PersonVotes> {
+        let person = self.people.get(name)?;
+        let mut dinners: Vec<String> = self
+            .dinners
+            .values()
+            .filter(|dinner| dinner.vote.as_deref() == Some(name))
+            .map(|dinner| dinner.name.clone())
+            .collect();
+        dinners.sort();
+
+        Some(PersonVotes {
+            name: name.to_string(),
+            votes: person.votes,
+            dinners,
+        })
+    }
+
+    // Get the votes `user` is allowed to see, `None` if they don't exist.
+    // Only their own unless `logged_in` says it's really them.
+    pub(crate) fn vote_summary(
+        &self,
+        user: &str,
+        logged_in: bool,
+    ) -> Option<VoteSummary> {
+        let mine = self.person_votes(user)?;
+        let everyone = if logged_in
+            && self.people[user].role.can(Permission::SeeEveryonesVotes)
+        {
+            let mut names: Vec<&String> = self.people.keys().collect();
+            names.sort();
+            Some(
+                names
+                    .into_iter()
+                    .filter_map(|name| self.person_votes(name))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        Some(VoteSummary { mine, everyone })
+    }
+}
@@ -0,0 +1,206 @@
+// The audit log: what's recorded for each change, and who can read it.
+
+mod common;
+
+use common::{household, household_with_tacos, roles_data, TestServer};
+use serde_json::json;
+use tide_server::roles::Role;
+
+// Member mia, who adds Tacos (ID 0), the owner olive and admin alice
+const ROLES: [Role; 3] = [Role::Member, Role::Owner, Role::Admin];
+
+async fn audit(server: &TestServer, query: &str) -> serde_json::Value {
+    let (status, entries) = server
+        .get_json(&format!("/api/admin/audit?user=alice{query}"))
+        .await;
+    assert_eq!(status, 200, "{}", entries);
+    entries
+}
+
+#[async_std::test]
+async fn changes_are_recorded() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.set_votes("olive", 3).await.is_ok());
+    assert!(server.delete_dinner("olive", "0").await.is_ok());
+    // Rejected changes aren't
+    assert!(!server.delete_dinner("olive", "0").await.is_ok());
+
+    let entries = audit(&server, "").await;
+    let actions: Vec<_> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        ["new_dinner", "vote", "set_votes", "delete_dinner"]
+    );
+
+    let reset = &entries[2];
+    assert_eq!(reset["actor"], "olive");
+    assert_eq!(reset["before"], json!({ "alice": 1, "mia": 0, "olive": 1 }));
+    assert_eq!(reset["after"], json!({ "alice": 3, "mia": 3, "olive": 3 }));
+    let deleted = &entries[3];
+    assert_eq!(deleted["actor"], "olive");
+    assert_eq!(deleted["dinner"], 0);
+    assert_eq!(deleted["before"]["name"], "Tacos");
+    assert_eq!(deleted["before"]["vote"], "mia");
+    assert_eq!(deleted["after"], serde_json::Value::Null);
+    assert!(deleted["at"].as_u64().unwrap() > 0);
+}
+
+#[async_std::test]
+async fn people_changes_leave_out_secrets() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    let body = json!({ "user": "mia", "password": "mia's secret" });
+    let (status, _) = server
+        .send_json("PUT", "/api/people/mia/password", body)
+        .await;
+    assert_eq!(status, 200);
+    let body = json!({ "user": "mia", "name": "maya" });
+    assert_eq!(
+        server.send_json("PATCH", "/api/people/mia", body).await.0,
+        200
+    );
+
+    let entries = audit(&server, "&person=mia").await;
+    assert_eq!(entries.as_array().unwrap().len(), 4);
+    let password = &entries[2];
+    assert_eq!(password["action"], "set_password");
+    assert_eq!(password["before"]["has_password"], false);
+    assert_eq!(password["after"]["has_password"], true);
+    assert!(!password.to_string().contains("argon2"));
+    // Renaming shows them under their new name after
+    let renamed = &entries[3];
+    assert_eq!(renamed["person"], "mia");
+    assert_eq!(renamed["before"]["votes"], 0);
+    assert_eq!(renamed["after"]["votes"], 0);
+}
+
+#[async_std::test]
+async fn dinner_edits_are_one_change() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.new_dinner("alice", "Soup").await.is_ok());
+
+    // Nothing changes if any of it can't
+    let body = json!({ "user": "alice", "short": "Crunchy", "name": "Soup" });
+    let (status, reply) =
+        server.send_json("PATCH", "/api/dinners/0", body).await;
+    assert_eq!(status, 409);
+    assert_eq!(reply["reason"], "name_taken");
+    let (_, tacos) = server.get_json("/api/dinners/0").await;
+    assert_eq!(tacos["short"], "-");
+
+    let body = json!({ "user": "alice", "short": "Crunchy", "name": "Nachos" });
+    let (status, _) = server.send_json("PATCH", "/api/dinners/0", body).await;
+    assert_eq!(status, 200);
+    let (_, nachos) = server.get_json("/api/dinners/0").await;
+    assert_eq!(nachos["name"], "Nachos");
+    assert_eq!(nachos["short"], "Crunchy");
+    let entries = audit(&server, "&dinner=0&action=edit_dinner").await;
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["after"]["name"], "Nachos");
+}
+
+#[async_std::test]
+async fn filters() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.new_dinner("alice", "Soup").await.is_ok());
+    assert!(server.set_votes("olive", 2).await.is_ok());
+
+    let count = |entries: serde_json::Value| entries.as_array().unwrap().len();
+    assert_eq!(count(audit(&server, "").await), 4);
+    assert_eq!(count(audit(&server, "&person=alice").await), 1);
+    assert_eq!(count(audit(&server, "&person=mia").await), 2);
+    assert_eq!(count(audit(&server, "&dinner=0").await), 2);
+    assert_eq!(count(audit(&server, "&dinner=1").await), 1);
+    assert_eq!(count(audit(&server, "&action=set_votes").await), 1);
+    assert_eq!(count(audit(&server, "&action=vote&person=mia").await), 1);
+
+    let at = audit(&server, "").await[3]["at"].as_u64().unwrap();
+    assert_eq!(count(audit(&server, &format!("&from={}", at + 1)).await), 0);
+    assert_eq!(count(audit(&server, &format!("&to={}", at + 1)).await), 4);
+    assert_eq!(count(audit(&server, "&to=1").await), 0);
+}
+
+#[async_std::test]
+async fn only_admins_can_read_it() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    let (status, reply) = server.get_json("/api/admin/audit?user=mia").await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "not_admin");
+    assert_eq!(server.get_json("/api/admin/audit?user=olive").await.0, 200);
+
+    let server = TestServer::with_data(
+        roles_data(&[("alice", 1, Role::Admin)]),
+        |config| config.legacy_unauthenticated = false,
+    );
+    let (status, _) = server.get_json("/api/admin/audit?user=alice").await;
+    assert_eq!(status, 401);
+}
+
+#[async_std::test]
+async fn restoring_keeps_the_log() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    let user = json!({ "user": "olive" });
+    let (status, backup) = server
+        .send_json("POST", "/api/admin/backups", user.clone())
+        .await;
+    assert_eq!(status, 201);
+    assert!(server.delete_dinner("olive", "0").await.is_ok());
+
+    let name = backup["name"].as_str().unwrap();
+    let path = format!("/api/admin/backups/{name}/restore");
+    assert_eq!(server.send_json("POST", &path, user).await.0, 200);
+
+    // Along with the restore itself
+    let entries = audit(&server, "").await;
+    assert_eq!(entries.as_array().unwrap().len(), 4);
+    let restore = &entries[3];
+    assert_eq!(restore["actor"], "olive");
+    assert_eq!(restore["action"], "restore");
+    let counts =
+        |dinners| json!({ "people": 3, "dinners": dinners, "invites": 0 });
+    assert_eq!(restore["before"], counts(0));
+    assert_eq!(restore["after"], counts(1));
+    let saved = std::fs::read_to_string(server.data_dir().join("audit"));
+    assert_eq!(saved.unwrap().lines().count(), 4);
+}
+
+#[async_std::test]
+async fn imports_are_recorded() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    let file = json!({ "dinners": [{ "name": "Soup" }, { "name": "Pie" }] });
+    let reply = server
+        .request(
+            "POST",
+            "/api/admin/import?user=alice",
+            Some("application/json"),
+            file.to_string().as_bytes(),
+        )
+        .await;
+    assert!(reply.is_ok(), "{:?}", reply);
+
+    let entries = audit(&server, "&action=import").await;
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["actor"], "alice");
+    assert_eq!(entries[0]["before"]["dinners"], 1);
+    assert_eq!(entries[0]["after"]["dinners"], 3);
+    assert_eq!(entries[0]["after"]["people"], 3);
+}
+
+#[async_std::test]
+async fn changes_are_made_without_the_log() {
+    let server = household(&[Role::Owner], |_| {});
+    // It can't be written while it's a directory
+    std::fs::create_dir(server.data_dir().join("audit")).unwrap();
+
+    assert!(server.new_dinner("olive", "Tacos").await.is_ok());
+    assert!(server.new_dinner("olive", "Soup").await.is_ok());
+    assert_eq!(server.reload().dinners.len(), 2);
+}
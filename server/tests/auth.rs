@@ -0,0 +1,229 @@
+// Logging in, and changes being refused without a session of the person
+// making them.
+
+mod common;
+
+use common::{people_data, TestServer};
+use serde_json::json;
+use tide_server::{auth, storage::Backend};
+
+// A server with logging in on, alice (an admin) and bob with passwords
+fn alice_and_bob(
+    change: impl FnOnce(&mut tide_server::config::Config),
+) -> TestServer {
+    let mut data = people_data(&[("alice", 0, true), ("bob", 1, false)]);
+    for (name, password) in
+        [("alice", "alice's secret"), ("bob", "bob's secret")]
+    {
+        data.people.get_mut(name).unwrap().password =
+            Some(auth::hash(password).unwrap());
+    }
+    TestServer::with_data(data, |config| {
+        config.legacy_unauthenticated = false;
+        change(config);
+    })
+}
+
+async fn make_tacos(server: &TestServer) {
+    server.log_in("alice", "alice's secret").await;
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    server.set_token(None);
+}
+
+#[async_std::test]
+async fn changes_need_a_login() {
+    let server = alice_and_bob(|_| {});
+    make_tacos(&server).await;
+
+    let reply = server.vote("bob", "0").await;
+    assert_eq!(reply.status, 401);
+    assert_eq!(reply.body, "rejected: not logged in");
+    let (status, _) = server
+        .send_json("POST", "/api/dinners/0/votes", json!({ "user": "bob" }))
+        .await;
+    assert_eq!(status, 401);
+    server.set_token(Some("made up"));
+    assert_eq!(server.vote("bob", "0").await.status, 401);
+
+    // Reading doesn't
+    assert_eq!(server.list().await.len(), 1);
+    assert_eq!(server.get_json("/api/people/bob").await.0, 200);
+
+    assert!(server.log_in("bob", "bob's secret").await.is_ok());
+    assert!(server.vote("bob", "0").await.is_ok());
+}
+
+#[async_std::test]
+async fn tokens_only_act_for_their_owner() {
+    let server = alice_and_bob(|_| {});
+    make_tacos(&server).await;
+    server.log_in("bob", "bob's secret").await;
+
+    let reply = server.new_dinner("alice", "Pizza").await;
+    assert_eq!(reply.status, 403);
+    assert_eq!(reply.body, "rejected: logged in as someone else");
+    let (status, _) = server.get_json("/api/admin/backups?user=alice").await;
+    assert_eq!(status, 403);
+
+    server.log_in("alice", "alice's secret").await;
+    assert_eq!(
+        server.get_json("/api/admin/backups?user=alice").await.0,
+        200
+    );
+}
+
+#[async_std::test]
+async fn wrong_passwords() {
+    let server = alice_and_bob(|_| {});
+
+    let reply = server.log_in("bob", "alice's secret").await;
+    assert_eq!(reply.status, 401);
+    assert_eq!(server.log_in("carol", "bob's secret").await.status, 401);
+    assert_eq!(server.token(), None);
+
+    // Until they stop for a while
+    for _ in 0..4 {
+        assert_eq!(server.log_in("bob", "guess").await.status, 401);
+    }
+    assert_eq!(server.log_in("bob", "bob's secret").await.status, 429);
+    assert!(server.log_in("alice", "alice's secret").await.is_ok());
+}
+
+#[async_std::test]
+async fn sign_up_with_a_password_or_pin() {
+    let server = alice_and_bob(|config| config.default_votes = 2);
+    server.log_in("alice", "alice's secret").await;
+    let code = server.invite("alice", json!({ "uses": 2 })).await;
+    server.set_token(None);
+
+    // Old apps can't send one
+    let reply = server.post(&format!("c carol\\{code}")).await;
+    assert_eq!(reply.status, 422);
+    for body in [
+        json!({ "name": "carol" }),
+        json!({ "name": "carol", "password": "short" }),
+        json!({ "name": "carol", "pin": "12ab" }),
+        json!({ "name": "carol", "pin": "123" }),
+        json!({ "name": "carol", "password": "long enough", "pin": "1234" }),
+    ] {
+        let mut body = body;
+        body["invite"] = code.as_str().into();
+        let (status, reply) =
+            server.send_json("POST", "/api/people", body).await;
+        assert_eq!(status, 422);
+        assert_eq!(reply["reason"], "invalid_password");
+    }
+
+    let person = json!({ "name": "carol", "pin": "1234", "invite": code });
+    assert_eq!(server.send_json("POST", "/api/people", person).await.0, 200);
+    let person =
+        json!({ "name": "dave", "password": "long enough", "invite": code });
+    assert_eq!(server.send_json("POST", "/api/people", person).await.0, 200);
+    assert!(server.log_in("carol", "1234").await.is_ok());
+    assert!(server.log_in("dave", "long enough").await.is_ok());
+
+    let (_, carol) = server.get_json("/api/people/carol").await;
+    assert_eq!(carol["votes"], 2);
+    assert_eq!(carol["has_password"], true);
+}
+
+#[async_std::test]
+async fn sessions_expire() {
+    let server = alice_and_bob(|config| config.session_ttl = 1);
+    server.log_in("alice", "alice's secret").await;
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+
+    async_std::task::sleep(std::time::Duration::from_secs(2)).await;
+    assert_eq!(server.new_dinner("alice", "Pizza").await.status, 401);
+}
+
+#[async_std::test]
+async fn log_out() {
+    let server = alice_and_bob(|_| {});
+    server.log_in("alice", "alice's secret").await;
+
+    let (status, _) = server.send_json("POST", "/api/logout", json!({})).await;
+    assert_eq!(status, 200);
+    assert_eq!(server.new_dinner("alice", "Tacos").await.status, 401);
+    let (status, _) = server.send_json("POST", "/api/logout", json!({})).await;
+    assert_eq!(status, 401);
+}
+
+#[async_std::test]
+async fn change_passwords() {
+    let server = alice_and_bob(|_| {});
+    server.log_in("bob", "bob's secret").await;
+    let old_session = server.token();
+    server.log_in("bob", "bob's secret").await;
+
+    let pin = json!({ "user": "bob", "pin": "9876" });
+    let (status, _) = server
+        .send_json("PUT", "/api/people/bob/password", pin)
+        .await;
+    assert_eq!(status, 200);
+    // The session that changed it still works, others don't
+    assert!(server.unvote("bob", "0").await.status != 401);
+    server.set_token(old_session.as_deref());
+    assert_eq!(server.unvote("bob", "0").await.status, 401);
+    assert_eq!(server.log_in("bob", "bob's secret").await.status, 401);
+    assert!(server.log_in("bob", "9876").await.is_ok());
+
+    // Only admins change other people's
+    let password = json!({ "user": "bob", "password": "taken over" });
+    let (status, _) = server
+        .send_json("PUT", "/api/people/alice/password", password)
+        .await;
+    assert_eq!(status, 403);
+    server.log_in("alice", "alice's secret").await;
+    let password = json!({ "user": "alice", "password": "reset by alice" });
+    let (status, _) = server
+        .send_json("PUT", "/api/people/bob/password", password)
+        .await;
+    assert_eq!(status, 200);
+    assert!(server.log_in("bob", "reset by alice").await.is_ok());
+}
+
+#[async_std::test]
+async fn passwords_are_saved_hashed() {
+    for storage in [Backend::Journal, Backend::Sqlite] {
+        let server = alice_and_bob(|config| config.storage = storage);
+        server.log_in("alice", "alice's secret").await;
+        let code = server.invite("alice", json!({})).await;
+        let person = json!({
+            "name": "carol",
+            "password": "carol's secret",
+            "invite": code,
+        });
+        assert_eq!(
+            server.send_json("POST", "/api/people", person).await.0,
+            200
+        );
+        server.log_in("bob", "bob's secret").await;
+        let pin = json!({ "user": "bob", "pin": "9876" });
+        server
+            .send_json("PUT", "/api/people/bob/password", pin)
+            .await;
+
+        let people = server.reload().people;
+        let carol = people["carol"].password.as_deref().unwrap();
+        assert!(carol.starts_with("$argon2"), "{}", carol);
+        assert!(!carol.contains("carol's secret"));
+        assert!(people["bob"].password.as_deref().unwrap() != "9876");
+    }
+}
+
+// Old apps keep working with logging in turned off
+#[async_std::test]
+async fn legacy_mode() {
+    let server = TestServer::with_people(&[("alice", 0, true)]);
+    assert!(server.new_user("bob").await.is_ok());
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+
+    // And people can set passwords ahead of turning it on
+    let password = json!({ "user": "bob", "password": "bob's secret" });
+    let (status, _) = server
+        .send_json("PUT", "/api/people/bob/password", password)
+        .await;
+    assert_eq!(status, 200);
+    assert!(server.log_in("bob", "bob's secret").await.is_ok());
+}
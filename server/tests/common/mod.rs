@@ -0,0 +1,541 @@
+// Runs the server on a free port for the tests, with helpers to send it
+// messages the way the app does.
+
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_std::{
+    io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+    net::TcpStream,
+};
+use tempfile::TempDir;
+use tide_server::{
+    auth, build_app, config::Config, roles::Role, storage::Backend,
+    DatabaseData, Person,
+};
+
+// Longest a test waits for the server
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TestServer {
+    addr: SocketAddr,
+    // Where the database is kept, deleted when the server is dropped
+    dir: TempDir,
+    storage: Backend,
+    // Session token sent with every request, if logged in
+    token: Mutex<Option<String>>,
+}
+
+// What the server replied
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn is_ok(&self) -> bool {
+        self.status == 200
+    }
+}
+
+// What the server replied, with its headers and a body that may not be text
+#[derive(Debug)]
+pub struct RawReply {
+    pub status: u16,
+    // Names are in lower case
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RawReply {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl TestServer {
+    // A server with an empty in-memory database.
+    pub fn start() -> Self {
+        Self::with_config(|config| config.storage = Backend::Memory)
+    }
+
+    // A server with settings changed by `change`, keeping the database in a
+    // temporary directory unless it changes the storage.
+    pub fn with_config(change: impl FnOnce(&mut Config)) -> Self {
+        Self::with_data(DatabaseData::default(), change)
+    }
+
+    // A server with `people` (name, votes, admin) and nothing else.
+    pub fn with_people(people: &[(&str, u16, bool)]) -> Self {
+        Self::with_data(people_data(people), |_| {})
+    }
+
+    // A server whose database starts out as `data`, which needs storage
+    // that's kept on disk.  Logging in is off unless `change` turns it on.
+    pub fn with_data(
+        data: DatabaseData,
+        change: impl FnOnce(&mut Config),
+    ) -> Self {
+        let dir = TempDir::new().expect("couldn't make a temp dir");
+        let mut config = Config {
+            listen: Vec::new(),
+            data_dir: dir.path().to_path_buf(),
+            storage: Backend::Journal,
+            backup_every: 0,
+            legacy_unauthenticated: true,
+            ..Config::default()
+        };
+        change(&mut config);
+        config
+            .storage
+            .open(dir.path())
+            .and_then(|mut storage| storage.snapshot(&data))
+            .expect("couldn't save the starting database");
+        let storage = config.storage;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_app(config).expect("couldn't build the app");
+        async_std::task::spawn(app.listen(listener));
+
+        Self {
+            addr,
+            dir,
+            storage,
+            token: Mutex::new(None),
+        }
+    }
+
+    // Log in, sending the session token with every request after.
+    pub async fn log_in(&self, name: &str, password: &str) -> Reply {
+        let body = serde_json::json!({ "name": name, "password": password });
+        let reply = self
+            .request(
+                "POST",
+                "/api/login",
+                Some("application/json"),
+                body.to_string().as_bytes(),
+            )
+            .await;
+        if reply.is_ok() {
+            let login: serde_json::Value = parse_json(&reply);
+            self.set_token(login["token"].as_str());
+        }
+        reply
+    }
+
+    // Send `token` with requests from now on, or nothing if `None`.
+    pub fn set_token(&self, token: Option<&str>) {
+        *self.token.lock().unwrap() = token.map(String::from);
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.token.lock().unwrap().clone()
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    // Load the database the way the server would after a restart.
+    pub fn reload(&self) -> DatabaseData {
+        self.storage
+            .open(self.dir.path())
+            .and_then(|mut storage| storage.load())
+            .expect("couldn't load the database")
+            .unwrap_or_default()
+    }
+
+    // Make an invite as `user` with `settings` (`role`, `votes`, `uses`,
+    // `expires_in`), returning its code.
+    pub async fn invite(
+        &self,
+        user: &str,
+        settings: serde_json::Value,
+    ) -> String {
+        let mut body = settings;
+        body["user"] = user.into();
+        let (status, invite) =
+            self.send_json("POST", "/api/invites", body).await;
+        assert_eq!(status, 201, "{}", invite);
+        invite["code"].as_str().unwrap().to_string()
+    }
+
+    // Send a legacy message.
+    pub async fn post(&self, message: &str) -> Reply {
+        self.request("POST", "/meal_vote", None, message.as_bytes())
+            .await
+    }
+
+    pub async fn get_json(&self, path: &str) -> (u16, serde_json::Value) {
+        let reply = self.request("GET", path, None, &[]).await;
+        (reply.status, parse_json(&reply))
+    }
+
+    pub async fn send_json(
+        &self,
+        method: &str,
+        path: &str,
+        body: serde_json::Value,
+    ) -> (u16, serde_json::Value) {
+        let body = body.to_string();
+        let reply = self
+            .request(method, path, Some("application/json"), body.as_bytes())
+            .await;
+        (reply.status, parse_json(&reply))
+    }
+
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Reply {
+        let headers: Vec<_> = content_type
+            .map(|mime| ("Content-Type", mime))
+            .into_iter()
+            .collect();
+        let reply = self.send(method, path, &headers, body).await;
+        Reply {
+            status: reply.status,
+            body: String::from_utf8(reply.body).expect("body isn't UTF-8"),
+        }
+    }
+
+    // Send a request with `headers`, keeping everything about the reply.
+    pub async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> RawReply {
+        async_std::future::timeout(TIMEOUT, async {
+            let mut stream = self.connect(method, path, headers, body).await;
+            let mut reader = BufReader::new(&mut stream);
+            let (status, headers) = read_head(&mut reader).await;
+            let chunked = headers.iter().any(|(name, value)| {
+                name == "transfer-encoding" && value == "chunked"
+            });
+            let mut body = Vec::new();
+            if chunked {
+                while let Some(chunk) = read_chunk(&mut reader).await {
+                    body.extend(chunk);
+                }
+            } else {
+                reader.read_to_end(&mut body).await.unwrap();
+            }
+            RawReply {
+                status,
+                headers,
+                body,
+            }
+        })
+        .await
+        .expect("the server didn't reply in time")
+    }
+
+    // Start listening for notifications.
+    pub async fn sse(&self) -> Events {
+        let stream = self.connect("GET", "/meal_vote/sse", &[], &[]).await;
+        let mut events = Events {
+            reader: BufReader::new(stream),
+            buffer: String::new(),
+        };
+        let (status, _) = read_head(&mut events.reader).await;
+        assert_eq!(status, 200);
+        events
+    }
+
+    async fn connect(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> TcpStream {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        let mut head = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\
+                Content-Length: {}\r\n",
+            self.addr,
+            body.len(),
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if let Some(token) = self.token() {
+            head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+        stream
+    }
+
+    // Legacy messages, one helper each
+
+    pub async fn list(&self) -> Vec<Vec<String>> {
+        let reply = self.post("l").await;
+        assert!(reply.is_ok(), "{:?}", reply);
+        reply
+            .body
+            .lines()
+            .map(|line| line.split('\\').map(String::from).collect())
+            .collect()
+    }
+
+    pub async fn get(&self, index: &str) -> Reply {
+        self.post(&format!("g {index}")).await
+    }
+
+    pub async fn get_votes(&self, user: &str) -> Reply {
+        self.post(&format!("h {user}")).await
+    }
+
+    pub async fn vote(&self, user: &str, index: &str) -> Reply {
+        self.post(&format!("v {user}\\{index}")).await
+    }
+
+    pub async fn unvote(&self, user: &str, index: &str) -> Reply {
+        self.post(&format!("u {user}\\{index}")).await
+    }
+
+    pub async fn view_votes(&self, user: &str) -> Reply {
+        self.post(&format!("a {user}")).await
+    }
+
+    pub async fn new_user(&self, name: &str) -> Reply {
+        self.post(&format!("c {name}")).await
+    }
+
+    pub async fn new_dinner(&self, user: &str, name: &str) -> Reply {
+        self.post(&format!("n {user}\\{name}")).await
+    }
+
+    pub async fn edit_shortname(
+        &self,
+        user: &str,
+        index: &str,
+        name: &str,
+    ) -> Reply {
+        self.post(&format!("s {user}\\{index}\\{name}")).await
+    }
+
+    pub async fn edit_longname(
+        &self,
+        user: &str,
+        index: &str,
+        name: &str,
+    ) -> Reply {
+        self.post(&format!("t {user}\\{index}\\{name}")).await
+    }
+
+    pub async fn edit_details(
+        &self,
+        user: &str,
+        index: &str,
+        details: &str,
+    ) -> Reply {
+        self.post(&format!("m {user}\\{index}\\{details}")).await
+    }
+
+    pub async fn delete_dinner(&self, user: &str, index: &str) -> Reply {
+        self.post(&format!("d {user}\\{index}")).await
+    }
+
+    pub async fn rate(
+        &self,
+        user: &str,
+        index: &str,
+        stars: u8,
+        note: Option<&str>,
+    ) -> Reply {
+        let mut message = format!("r {user}\\{index}\\{stars}");
+        if let Some(note) = note {
+            message.push('\\');
+            message.push_str(note);
+        }
+        self.post(&message).await
+    }
+
+    pub async fn analytics(&self, user: &str, index: Option<&str>) -> Reply {
+        match index {
+            Some(index) => self.post(&format!("y {user}\\{index}")).await,
+            None => self.post(&format!("y {user}")).await,
+        }
+    }
+
+    pub async fn serve(&self, user: &str, index: &str) -> Reply {
+        self.post(&format!("e {user}\\{index}")).await
+    }
+
+    pub async fn set_votes(&self, user: &str, votes: u16) -> Reply {
+        self.post(&format!("z {user}\\{votes}")).await
+    }
+}
+
+// Server sent events as they come in
+pub struct Events {
+    reader: BufReader<TcpStream>,
+    // Read but not yet returned
+    buffer: String,
+}
+
+impl Events {
+    // The next event as `(event, data)`, `None` once the server is done.
+    pub async fn next(&mut self) -> Option<(String, String)> {
+        async_std::future::timeout(TIMEOUT, async {
+            loop {
+                if let Some(end) = self.buffer.find("\n\n") {
+                    let event: String = self.buffer.drain(..end + 2).collect();
+                    return Some(parse_event(&event));
+                }
+                let chunk = read_chunk(&mut self.reader).await?;
+                self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+        })
+        .await
+        .expect("no event in time")
+    }
+}
+
+fn parse_event(event: &str) -> (String, String) {
+    let mut name = String::new();
+    let mut data = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim_start().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.trim_start());
+        }
+    }
+    (name, data.join("\n"))
+}
+
+fn parse_json(reply: &Reply) -> serde_json::Value {
+    if reply.body.is_empty() {
+        return serde_json::Value::Null;
+    }
+    serde_json::from_str(&reply.body)
+        .unwrap_or_else(|e| panic!("{e} in {:?}", reply.body))
+}
+
+// Read the status line and headers, with the header names in lower case.
+async fn read_head<R: BufReadExt + Unpin>(
+    reader: &mut R,
+) -> (u16, Vec<(String, String)>) {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or_else(|| panic!("bad status line {:?}", line));
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+    }
+    (status, headers)
+}
+
+// Read one chunk of a chunked body, `None` after the last.
+async fn read_chunk<R: BufReadExt + ReadExt + Unpin>(
+    reader: &mut R,
+) -> Option<Vec<u8>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let len = usize::from_str_radix(line.trim_end(), 16).ok()?;
+    let mut chunk = vec![0; len + 2];
+    reader.read_exact(&mut chunk).await.ok()?;
+    chunk.truncate(len);
+    (len != 0).then_some(chunk)
+}
+
+// A database with just these people (name, votes, admin).
+pub fn people_data(people: &[(&str, u16, bool)]) -> DatabaseData {
+    let people: Vec<_> = people
+        .iter()
+        .map(|(name, votes, admin)| (*name, *votes, Role::from_admin(*admin)))
+        .collect();
+    roles_data(&people)
+}
+
+// Who has each role in `household`
+const HOUSEHOLD: [(&str, Role); 5] = [
+    ("olive", Role::Owner),
+    ("alice", Role::Admin),
+    ("mia", Role::Member),
+    ("kid", Role::Child),
+    ("gus", Role::Guest),
+];
+
+// A server with one person for each of `roles`, all with a vote: the owner
+// olive (whose password is "olive's secret"), admin alice, member mia, child
+// kid and guest gus.  `change` is made to the config.
+pub fn household(
+    roles: &[Role],
+    change: impl FnOnce(&mut Config),
+) -> TestServer {
+    let people: Vec<_> = HOUSEHOLD
+        .iter()
+        .filter(|(_, role)| roles.contains(role))
+        .map(|(name, role)| (*name, 1, *role))
+        .collect();
+    let mut data = roles_data(&people);
+    if let Some(olive) = data.people.get_mut("olive") {
+        olive.password = Some(auth::hash("olive's secret").unwrap());
+    }
+    TestServer::with_data(data, change)
+}
+
+// `household` with Tacos (ID 0), added by whoever has the first of `roles`.
+pub async fn household_with_tacos(
+    roles: &[Role],
+    change: impl FnOnce(&mut Config),
+) -> TestServer {
+    let server = household(roles, change);
+    let cook = HOUSEHOLD
+        .iter()
+        .find(|(_, role)| Some(role) == roles.first());
+    let reply = server.new_dinner(cook.unwrap().0, "Tacos").await;
+    assert!(reply.is_ok(), "{:?}", reply);
+    server
+}
+
+// A database with just these people (name, votes, role).
+pub fn roles_data(people: &[(&str, u16, Role)]) -> DatabaseData {
+    let mut data = DatabaseData::default();
+    for (name, votes, role) in people {
+        data.people.insert(
+            name.to_string(),
+            Person {
+                votes: *votes,
+                role: *role,
+                password: None,
+                deactivated: false,
+            },
+        );
+    }
+    data
+}
@@ -0,0 +1,165 @@
+// Invite codes: who can make them, and what the accounts made with them get.
+
+mod common;
+
+use common::{household, TestServer};
+use serde_json::json;
+use tide_server::{
+    apply_event, auth, roles::Role, storage::Backend, DatabaseData, DbEvent,
+};
+
+// The owner olive and member mia
+const ROLES: [Role; 2] = [Role::Owner, Role::Member];
+
+async fn sign_up(
+    server: &TestServer,
+    name: &str,
+    invite: Option<&str>,
+) -> (u16, serde_json::Value) {
+    let person = json!({ "name": name, "pin": "1234", "invite": invite });
+    server.send_json("POST", "/api/people", person).await
+}
+
+#[async_std::test]
+async fn accounts_need_an_invite() {
+    let server =
+        household(&ROLES, |config| config.legacy_unauthenticated = false);
+    server.log_in("olive", "olive's secret").await;
+    let code = server.invite("olive", json!({})).await;
+    server.set_token(None);
+
+    let (status, reply) = sign_up(&server, "carol", None).await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "no_invite");
+    let reply = server.new_user("carol").await;
+    assert_eq!(reply.status, 403);
+    assert_eq!(reply.body, "rejected: an invite code is needed");
+    let (status, reply) = sign_up(&server, "carol", Some("ABCD-EFGH")).await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "wrong_invite");
+
+    // A taken name doesn't use it up
+    let (status, reply) = sign_up(&server, "mia", Some(&code)).await;
+    assert_eq!(status, 409);
+    assert_eq!(reply["reason"], "name_taken");
+    assert_eq!(sign_up(&server, "carol", Some(&code)).await.0, 200);
+    assert!(server.log_in("carol", "1234").await.is_ok());
+}
+
+// Signing up is checked when it's applied, so two people signing up at
+// once can't both skip the invite
+#[test]
+fn only_the_first_account_skips_the_invite() {
+    let mut data = DatabaseData::default();
+    let sign_up = |name: &str| DbEvent::NewUser {
+        name: name.into(),
+        votes: 1,
+        password: Some(auth::hash("1234").unwrap()),
+        invite: None,
+        invite_needed: true,
+    };
+
+    assert!(apply_event(&mut data, sign_up("olive"), 1).is_applied());
+    let outcome = apply_event(&mut data, sign_up("carol"), 1);
+    assert_eq!(outcome.to_string(), "rejected: an invite code is needed");
+    assert_eq!(data.people.len(), 1);
+    assert_eq!(data.people["olive"].role, Role::Owner);
+}
+
+#[async_std::test]
+async fn invites_set_role_and_votes() {
+    let server = household(&ROLES, |config| config.default_votes = 2);
+
+    let code = server
+        .invite("olive", json!({ "role": "child", "votes": 5 }))
+        .await;
+    assert_eq!(sign_up(&server, "kid", Some(&code)).await.0, 200);
+    let (_, kid) = server.get_json("/api/people/kid").await;
+    assert_eq!(kid["role"], "child");
+    assert_eq!(kid["votes"], 5);
+
+    // Old apps send the code after the name
+    let code = server.invite("olive", json!({})).await;
+    assert!(server.post(&format!("c carol\\{code}")).await.is_ok());
+    assert_eq!(server.get_votes("carol").await.body, "2\\FALSE");
+    let (_, carol) = server.get_json("/api/people/carol").await;
+    assert_eq!(carol["role"], "member");
+}
+
+#[async_std::test]
+async fn uses_and_expiry() {
+    let server = household(&ROLES, |_| {});
+
+    let once = server.invite("olive", json!({})).await;
+    let twice = server.invite("olive", json!({ "uses": 2 })).await;
+    let expired = server.invite("olive", json!({ "expires_in": 0 })).await;
+    assert_eq!(sign_up(&server, "a", Some(&once)).await.0, 200);
+    assert_eq!(sign_up(&server, "b", Some(&once)).await.0, 403);
+    // However it's typed in
+    let typed = format!(" {} ", twice.to_lowercase());
+    assert_eq!(sign_up(&server, "b", Some(&typed)).await.0, 200);
+    assert_eq!(sign_up(&server, "c", Some(&twice)).await.0, 200);
+    assert_eq!(sign_up(&server, "d", Some(&twice)).await.0, 403);
+    assert_eq!(sign_up(&server, "d", Some(&expired)).await.0, 403);
+
+    let (status, invites) = server.get_json("/api/invites?user=olive").await;
+    assert_eq!(status, 200);
+    assert_eq!(invites, json!([]));
+}
+
+#[async_std::test]
+async fn managing_invites() {
+    let server = household(&ROLES, |_| {});
+
+    let (status, _) = server
+        .send_json("POST", "/api/invites", json!({ "user": "mia" }))
+        .await;
+    assert_eq!(status, 403);
+    assert_eq!(server.get_json("/api/invites?user=mia").await.0, 403);
+    for settings in [json!({ "role": "owner" }), json!({ "uses": 0 })] {
+        let mut body = settings;
+        body["user"] = "olive".into();
+        let (status, reply) =
+            server.send_json("POST", "/api/invites", body).await;
+        assert_eq!(status, 422);
+        assert_eq!(reply["reason"], "invalid_invite");
+    }
+
+    let later = server.invite("olive", json!({ "uses": 3 })).await;
+    let soon = server
+        .invite("olive", json!({ "role": "guest", "expires_in": 60 }))
+        .await;
+    let (_, invites) = server.get_json("/api/invites?user=olive").await;
+    assert_eq!(invites[0]["code"], soon.as_str());
+    assert_eq!(invites[0]["role"], "guest");
+    assert_eq!(invites[0]["by"], "olive");
+    assert_eq!(invites[1]["code"], later.as_str());
+    assert_eq!(invites[1]["uses"], 3);
+    assert_eq!(invites[1]["expires"], serde_json::Value::Null);
+
+    let path = format!("/api/invites/{later}");
+    let user = json!({ "user": "olive" });
+    assert_eq!(server.send_json("DELETE", &path, user.clone()).await.0, 200);
+    assert_eq!(server.send_json("DELETE", &path, user).await.0, 404);
+    assert_eq!(sign_up(&server, "carol", Some(&later)).await.0, 403);
+}
+
+#[async_std::test]
+async fn invites_are_saved() {
+    for storage in [Backend::Journal, Backend::Muon, Backend::Sqlite] {
+        let server = household(&ROLES, |config| config.storage = storage);
+        let used = server
+            .invite("olive", json!({ "role": "guest", "uses": 2 }))
+            .await;
+        let unused = server
+            .invite("olive", json!({ "votes": 4, "expires_in": 60 }))
+            .await;
+        assert_eq!(sign_up(&server, "gus", Some(&used)).await.0, 200);
+
+        let data = server.reload();
+        assert_eq!(data.people["gus"].role, Role::Guest, "{:?}", storage);
+        assert_eq!(data.invites[&used].uses, 1, "{:?}", storage);
+        assert_eq!(data.invites[&unused].votes, 4, "{:?}", storage);
+        assert!(data.invites[&unused].expires.is_some());
+    }
+}
@@ -0,0 +1,361 @@
+// The messages the app POSTs to `/meal_vote`.
+
+mod common;
+
+use common::{people_data, TestServer};
+use tide_server::storage::Backend;
+
+// An admin, Alice, who added Tacos (ID 0) and Pizza (ID 1), and Bob, with
+// one vote.
+async fn tacos_and_pizza() -> TestServer {
+    let server =
+        TestServer::with_people(&[("alice", 0, true), ("bob", 1, false)]);
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    assert!(server.new_dinner("alice", "Pizza").await.is_ok());
+    server
+}
+
+fn names(list: &[Vec<String>]) -> Vec<&str> {
+    let mut names: Vec<&str> =
+        list.iter().map(|line| line[0].as_str()).collect();
+    names.sort();
+    names
+}
+
+#[async_std::test]
+async fn voting_uses_up_votes() {
+    let server = tacos_and_pizza().await;
+
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+    assert_eq!(server.get_votes("bob").await.body, "0\\FALSE");
+    let list = server.list().await;
+    let tacos = list.iter().find(|line| line[0] == "Tacos").unwrap();
+    assert_eq!(tacos, &["Tacos", "-", "bob"]);
+
+    let reply = server.vote("bob", "Pizza").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: no votes left");
+
+    assert!(server.unvote("bob", "Tacos").await.is_ok());
+    assert_eq!(server.get_votes("bob").await.body, "1\\FALSE");
+    assert!(server.vote("bob", "1").await.is_ok());
+    assert_eq!(server.view_votes("bob").await.body, "0\\Pizza");
+}
+
+#[async_std::test]
+async fn one_vote_per_dinner() {
+    let server = tacos_and_pizza().await;
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+
+    let reply = server.vote("alice", "Tacos").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: dinner already has a vote");
+
+    let reply = server.unvote("bob", "Pizza").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: dinner has no vote");
+}
+
+#[async_std::test]
+async fn only_the_voter_or_an_admin_can_unvote() {
+    let server = TestServer::with_people(&[
+        ("alice", 0, true),
+        ("bob", 1, false),
+        ("carol", 1, false),
+    ]);
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+
+    let reply = server.unvote("carol", "Tacos").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: vote belongs to someone else");
+
+    assert!(server.unvote("alice", "Tacos").await.is_ok());
+    // The vote goes back to whoever spent it
+    assert_eq!(server.get_votes("bob").await.body, "1\\FALSE");
+}
+
+#[async_std::test]
+async fn admins_vote_for_free() {
+    let server = TestServer::with_people(&[("alice", 1, true)]);
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    assert!(server.new_dinner("alice", "Pizza").await.is_ok());
+
+    assert!(server.vote("alice", "Tacos").await.is_ok());
+    assert!(server.vote("alice", "Pizza").await.is_ok());
+    assert_eq!(server.get_votes("alice").await.body, "1\\TRUE");
+}
+
+#[async_std::test]
+async fn admin_checks() {
+    let server = tacos_and_pizza().await;
+
+    // Anyone but children and guests can add dinners, see `roles.rs`
+    for reply in [
+        server.edit_shortname("bob", "Tacos", "Soup").await,
+        server.edit_longname("bob", "Tacos", "Soup").await,
+        server.edit_details("bob", "Tacos", "Soup").await,
+        server.delete_dinner("bob", "Tacos").await,
+        server.serve("bob", "Tacos").await,
+        server.set_votes("bob", 9).await,
+    ] {
+        assert_eq!(reply.status, 403, "{reply:?}");
+        assert_eq!(reply.body, "rejected: only admins can do that");
+    }
+
+    let reply = server.new_dinner("nobody", "Soup").await;
+    assert_eq!(reply.status, 404);
+    assert_eq!(reply.body, "no such person");
+
+    assert_eq!(names(&server.list().await), ["Pizza", "Tacos"]);
+    assert_eq!(server.get_votes("bob").await.body, "1\\FALSE");
+    assert!(server.set_votes("alice", 3).await.is_ok());
+    assert_eq!(server.get_votes("bob").await.body, "3\\FALSE");
+}
+
+#[async_std::test]
+async fn rename() {
+    let server = tacos_and_pizza().await;
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+
+    assert!(server
+        .edit_shortname("alice", "Tacos", "Burritos")
+        .await
+        .is_ok());
+    assert_eq!(names(&server.list().await), ["Burritos", "Pizza"]);
+    assert_eq!(server.get("Tacos").await.body, "");
+    // Same dinner, so the vote and ID stay
+    assert_eq!(server.view_votes("bob").await.body, "0\\Burritos");
+    assert!(server
+        .edit_longname("alice", "0", "Bean burritos")
+        .await
+        .is_ok());
+    assert!(server
+        .get("Burritos")
+        .await
+        .body
+        .starts_with("Bean burritos\r"));
+
+    let reply = server.edit_shortname("alice", "Burritos", "Pizza").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: name is already taken");
+
+    let reply = server.edit_shortname("alice", "Tacos", "Soup").await;
+    assert_eq!(reply.status, 404);
+    assert_eq!(reply.body, "no such dinner");
+}
+
+#[async_std::test]
+async fn delete() {
+    let server = tacos_and_pizza().await;
+
+    assert!(server.delete_dinner("alice", "Tacos").await.is_ok());
+    assert_eq!(names(&server.list().await), ["Pizza"]);
+    assert_eq!(server.get("Tacos").await.body, "");
+    assert_eq!(server.vote("bob", "Tacos").await.status, 404);
+    assert_eq!(server.delete_dinner("alice", "Tacos").await.status, 404);
+
+    // The name can be used again, for a new dinner
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    assert_eq!(server.get("0").await.body, "");
+    assert!(server.vote("bob", "2").await.is_ok());
+}
+
+#[async_std::test]
+async fn new_users() {
+    let server =
+        TestServer::with_data(people_data(&[("alice", 0, true)]), |config| {
+            config.default_votes = 2
+        });
+
+    assert!(server.new_user("dan").await.is_ok());
+    assert_eq!(server.get_votes("dan").await.body, "2\\FALSE");
+    let reply = server.new_user("dan").await;
+    assert_eq!(reply.status, 409);
+    assert_eq!(reply.body, "rejected: name is already taken");
+
+    // Unknown people have no votes
+    assert_eq!(server.get_votes("erin").await.body, "");
+}
+
+#[async_std::test]
+async fn details_and_ratings() {
+    let server = tacos_and_pizza().await;
+    assert!(server
+        .edit_longname("alice", "Tacos", "Fish tacos")
+        .await
+        .is_ok());
+    assert!(server
+        .edit_details("alice", "Tacos", "With lime")
+        .await
+        .is_ok());
+    assert!(server
+        .rate("bob", "Tacos", 4, Some("good\\crunchy"))
+        .await
+        .is_ok());
+    assert!(server.rate("alice", "Tacos", 5, None).await.is_ok());
+
+    assert_eq!(
+        server.get("Tacos").await.body,
+        "Fish tacos\rWith lime\r\r2\\4.5\\0\\0\\0\\1\\1",
+    );
+
+    let reply = server.rate("bob", "Tacos", 6, None).await;
+    assert_eq!(reply.status, 422);
+    assert!(server.rate("bob", "Tacos", 0, None).await.is_ok());
+    assert!(server
+        .get("Tacos")
+        .await
+        .body
+        .ends_with("\r\r1\\5.0\\0\\0\\0\\0\\1"));
+}
+
+#[async_std::test]
+async fn serving_uses_up_the_vote() {
+    let server = tacos_and_pizza().await;
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+
+    assert!(server.serve("alice", "Tacos").await.is_ok());
+    assert_eq!(server.get_votes("bob").await.body, "0\\FALSE");
+    assert_eq!(server.vote("bob", "Pizza").await.status, 409);
+    assert_eq!(server.serve("alice", "Tacos").await.status, 409);
+
+    let analytics = server.analytics("bob", Some("Tacos")).await;
+    assert!(
+        analytics.body.starts_with("d\\Tacos\\1\\1\\"),
+        "{:?}",
+        analytics
+    );
+}
+
+#[async_std::test]
+async fn taken_back_votes_dont_count() {
+    let server = tacos_and_pizza().await;
+    for _ in 0..3 {
+        assert!(server.vote("bob", "Tacos").await.is_ok());
+        assert!(server.unvote("bob", "Tacos").await.is_ok());
+    }
+    assert!(server.vote("bob", "Pizza").await.is_ok());
+
+    let (_, analytics) = server.get_json("/api/analytics").await;
+    let votes = |name: &str| {
+        let dinners = analytics["dinners"].as_array().unwrap();
+        let dinner = dinners.iter().find(|dinner| dinner["name"] == name);
+        dinner.unwrap()["votes"].clone()
+    };
+    assert_eq!(votes("Tacos"), 0);
+    assert_eq!(votes("Pizza"), 1);
+    assert_eq!(analytics["voters"][0]["name"], "bob");
+    assert_eq!(analytics["voters"][0]["votes"], 1);
+    let (_, pizza) = server.get_json("/api/dinners/1/analytics").await;
+    assert_eq!(pizza["share"][0]["share"], 1.0);
+}
+
+#[async_std::test]
+async fn bad_messages() {
+    let server = TestServer::start();
+
+    for message in ["", "q", "v bob", "r bob\\Tacos\\lots"] {
+        let reply = server.post(message).await;
+        assert_eq!(reply.status, 400, "{message:?} got {reply:?}");
+        assert!(!reply.body.is_empty());
+    }
+}
+
+#[async_std::test]
+async fn changes_are_saved() {
+    let server = tacos_and_pizza().await;
+    assert!(server.vote("bob", "Pizza").await.is_ok());
+    assert!(server.delete_dinner("alice", "Tacos").await.is_ok());
+
+    let data = server.reload();
+    let pizza = data.dinner("Pizza").unwrap();
+    assert_eq!(pizza.vote.as_deref(), Some("bob"));
+    assert!(data.dinner("Tacos").is_none());
+    assert_eq!(data.people["bob"].votes, 0);
+    assert_eq!(data.next_dinner, 2);
+}
+
+#[async_std::test]
+async fn given_back_votes_are_saved_in_sqlite() {
+    let people = people_data(&[("alice", 0, true), ("bob", 1, false)]);
+    let server = TestServer::with_data(people, |config| {
+        config.storage = Backend::Sqlite;
+    });
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+    assert!(server.unvote("alice", "Tacos").await.is_ok());
+
+    let data = server.reload();
+    assert_eq!(data.people["bob"].votes, 1);
+    assert_eq!(data.dinner("Tacos").unwrap().vote, None);
+}
+
+#[async_std::test]
+async fn unicode_names() {
+    let server = TestServer::with_people(&[("Zoë", 0, true)]);
+    assert!(server.new_user("🍕 fan").await.is_ok());
+    assert!(server.set_votes("Zoë", 1).await.is_ok());
+    assert!(server.new_dinner("Zoë", "Crème brûlée 🍮").await.is_ok());
+
+    assert!(server.vote("🍕 fan", "Crème brûlée 🍮").await.is_ok());
+    assert_eq!(server.list().await, [["Crème brûlée 🍮", "-", "🍕 fan"]]);
+    assert_eq!(server.view_votes("🍕 fan").await.body, "0\\Crème brûlée 🍮");
+    assert!(server
+        .edit_shortname("Zoë", "Crème brûlée 🍮", "Ñoquis")
+        .await
+        .is_ok());
+    assert!(server.get("Ñoquis").await.body.starts_with("-\r-\r\r0\\"));
+}
+
+#[async_std::test]
+async fn short_and_odd_messages() {
+    let server = TestServer::start();
+
+    for message in ["g", "v", "é", "🍕", "g\\", "gé", "l 🍕", "v 🍕", "z a\\-1"]
+    {
+        let reply = server.post(message).await;
+        assert_eq!(reply.status, 400, "{:?} got {:?}", message, reply);
+    }
+    let reply = server.request("POST", "/meal_vote", None, b"c \xFF").await;
+    assert_eq!(reply.status, 400);
+}
+
+#[async_std::test]
+async fn invalid_names() {
+    let server = TestServer::with_people(&[("alice", 0, true)]);
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+
+    for reply in [
+        server.new_user(" ").await,
+        server.new_user("bob\nsmith").await,
+        server.new_dinner("alice", "Fish\\chips").await,
+        server.edit_shortname("alice", "Tacos", "\t").await,
+    ] {
+        assert_eq!(reply.status, 422, "{:?}", reply);
+        assert_eq!(
+            reply.body,
+            "rejected: name is blank or has a backslash or control character",
+        );
+    }
+    // After a backslash is the invite code, not more of the name
+    let reply = server.new_user("bob\\smith").await;
+    assert_eq!(reply.status, 403, "{:?}", reply);
+    assert_eq!(
+        reply.body,
+        "rejected: wrong, used up or expired invite code"
+    );
+    assert_eq!(names(&server.list().await), ["Tacos"]);
+    assert!(!server.reload().people.contains_key("bob"));
+}
+
+#[async_std::test]
+async fn votes_dont_overflow() {
+    let server = tacos_and_pizza().await;
+    assert!(server.vote("bob", "Tacos").await.is_ok());
+    assert!(server.set_votes("alice", u16::MAX).await.is_ok());
+
+    assert!(server.unvote("bob", "Tacos").await.is_ok());
+    assert_eq!(server.get_votes("bob").await.body, "65535\\FALSE");
+    assert!(server.post("l").await.is_ok());
+}
@@ -0,0 +1,151 @@
+// Loading database files written by older versions, and printing what
+// migrating them would change.
+
+use std::{path::Path, process::Command};
+
+use tempfile::TempDir;
+use tide_server::{roles::Role, storage::Backend, DatabaseData};
+
+// Dinners keyed by name, and history refers to them by name
+const VERSION_1: &str = "\
+dinners: Tacos
+  value: Crunchy
+    long: Beef and cheese
+    vote: bob
+dinners: Soup
+  value: Hot
+    long: Tomato
+people: bob
+  value: 0
+    admin: false
+people: alice
+  value: 2
+    admin: true
+history: bob
+  dinner: Tacos
+  kind: vote
+  at: 100
+history: bob
+  dinner: Pizza
+  kind: vote
+  at: 50
+";
+
+// Dinners keyed by ID, people only say if they're an admin
+const VERSION_2: &str = "\
+dinners: 4
+  value: Hot
+    long: Tomato
+    vote: bob
+  name: Soup
+people: bob
+  value: 0
+    admin: false
+people: alice
+  value: 2
+    admin: true
+history: bob
+  dinner: 4
+  kind: vote
+  at: 100
+next_dinner: 7
+";
+
+// A data directory with `database` written as `contents`
+fn data_dir(contents: &str) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("database"), contents).unwrap();
+    dir
+}
+
+fn load(dir: &Path) -> DatabaseData {
+    Backend::Muon.open(dir).unwrap().load().unwrap().unwrap()
+}
+
+fn dry_run(dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_tide-server"))
+        .env_clear()
+        .arg("--migrate-dry-run")
+        .arg("--data-dir")
+        .arg(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn from_version_1() {
+    let dir = data_dir(VERSION_1);
+    let data = load(dir.path());
+
+    assert_eq!(data.dinners[&0].name, "Tacos");
+    assert_eq!(data.dinners[&0].short, "Crunchy");
+    assert_eq!(data.dinners[&0].vote.as_deref(), Some("bob"));
+    assert_eq!(data.dinners[&1].name, "Soup");
+    assert_eq!(data.next_dinner, 2);
+    // Pizza was deleted, so its history can't be kept
+    assert_eq!(data.history.len(), 1);
+    assert_eq!(data.history[0].dinner, 0);
+    assert_eq!(data.people["alice"].role, Role::Admin);
+    assert_eq!(data.people["bob"].role, Role::Member);
+
+    // The migrated file is saved, keeping the old one as the backup
+    let saved = std::fs::read_to_string(dir.path().join("database")).unwrap();
+    assert!(saved.contains("version: 3"), "{}", saved);
+    let backup = std::fs::read_to_string(dir.path().join("database.bak"));
+    assert_eq!(backup.unwrap(), VERSION_1);
+    assert_eq!(load(dir.path()).dinners[&1].name, "Soup");
+}
+
+#[test]
+fn from_version_2() {
+    let dir = data_dir(VERSION_2);
+    let data = load(dir.path());
+
+    assert_eq!(data.dinners[&4].name, "Soup");
+    assert_eq!(data.next_dinner, 7);
+    assert_eq!(data.history[0].dinner, 4);
+    assert_eq!(data.people["alice"].role, Role::Admin);
+    assert_eq!(data.people["bob"].role, Role::Member);
+    let saved = std::fs::read_to_string(dir.path().join("database")).unwrap();
+    assert!(saved.contains("version: 3"), "{}", saved);
+}
+
+#[test]
+fn from_a_newer_version() {
+    let dir = data_dir(&format!("{VERSION_2}version: 99\n"));
+
+    let Err(error) = Backend::Muon.open(dir.path()).unwrap().load() else {
+        panic!("a newer version loaded");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    // It's left for a newer server
+    let saved = std::fs::read_to_string(dir.path().join("database")).unwrap();
+    assert!(saved.contains("version: 99"));
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    let dir = data_dir(VERSION_1);
+
+    let printed = dry_run(dir.path());
+    assert!(
+        printed.contains("is version 1, migrating to version 3 would:"),
+        "{}",
+        printed,
+    );
+    assert!(printed.contains("- 1 to 2: give dinner `Tacos` ID 0\n"));
+    assert!(printed.contains("- 1 to 2: give dinner `Soup` ID 1\n"));
+    assert!(printed
+        .contains("- 1 to 2: drop 1 history events for deleted dinners\n"));
+    assert!(printed.contains("- 2 to 3: make `alice` admin\n"));
+    assert!(printed.contains("- 2 to 3: make `bob` member\n"));
+    let saved = std::fs::read_to_string(dir.path().join("database")).unwrap();
+    assert_eq!(saved, VERSION_1);
+    assert!(!dir.path().join("database.bak").exists());
+
+    // Once it's migrated there's nothing left to do
+    load(dir.path());
+    assert!(dry_run(dir.path()).contains("is version 3, nothing to do"));
+}
@@ -0,0 +1,387 @@
+// Making the owner, and changing, renaming, removing and deactivating people.
+
+mod common;
+
+use common::{household_with_tacos, roles_data, TestServer};
+use serde_json::json;
+use tide_server::{auth, roles::Role, storage::Backend};
+
+// The owner olive, who adds Tacos (ID 0), admin alice and member mia
+const ROLES: [Role; 3] = [Role::Owner, Role::Admin, Role::Member];
+
+async fn set_role(
+    server: &TestServer,
+    user: &str,
+    name: &str,
+    role: &str,
+) -> (u16, serde_json::Value) {
+    let body = json!({ "user": user, "role": role });
+    server
+        .send_json("PUT", &format!("/api/people/{name}/role"), body)
+        .await
+}
+
+async fn role(server: &TestServer, name: &str) -> serde_json::Value {
+    server.get_json(&format!("/api/people/{name}")).await.1["role"].clone()
+}
+
+#[async_std::test]
+async fn first_account_is_the_owner() {
+    let server = TestServer::with_config(|config| {
+        config.legacy_unauthenticated = false;
+    });
+
+    let person = json!({ "name": "olive", "password": "olive's secret" });
+    assert_eq!(server.send_json("POST", "/api/people", person).await.0, 200);
+    server.log_in("olive", "olive's secret").await;
+    let code = server.invite("olive", json!({})).await;
+    let person =
+        json!({ "name": "mia", "password": "mia's secret", "invite": code });
+    assert_eq!(server.send_json("POST", "/api/people", person).await.0, 200);
+    assert_eq!(role(&server, "olive").await, "owner");
+    assert_eq!(role(&server, "mia").await, "member");
+}
+
+#[async_std::test]
+async fn setup_token() {
+    let mut data = roles_data(&[
+        ("alice", 1, Role::Admin),
+        ("mia", 1, Role::Member),
+        ("dan", 1, Role::Member),
+    ]);
+    data.people.get_mut("dan").unwrap().deactivated = true;
+    let server = TestServer::with_data(data, |config| {
+        config.legacy_unauthenticated = false
+    });
+    let path = server.data_dir().join("setup_token");
+    let token = std::fs::read_to_string(&path).unwrap();
+
+    let body = json!({ "token": "guess", "name": "mia" });
+    let (status, reply) = server.send_json("POST", "/api/setup", body).await;
+    assert_eq!(status, 401);
+    assert_eq!(reply["reason"], "wrong_setup_token");
+    let body = json!({ "token": token, "name": "mia", "password": "short" });
+    let (status, _) = server.send_json("POST", "/api/setup", body).await;
+    assert_eq!(status, 422);
+    let body = json!({ "token": token, "name": "olive" });
+    assert_eq!(server.send_json("POST", "/api/setup", body).await.0, 404);
+    // Nobody could log in as the owner
+    let body = json!({ "token": token, "name": "dan", "pin": "1357" });
+    let (status, reply) = server.send_json("POST", "/api/setup", body).await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "deactivated");
+    assert_eq!(role(&server, "dan").await, "member");
+
+    // It works once
+    let body = json!({ "token": token, "name": "mia", "pin": "2468" });
+    assert_eq!(server.send_json("POST", "/api/setup", body).await.0, 200);
+    assert_eq!(role(&server, "mia").await, "owner");
+    assert!(server.log_in("mia", "2468").await.is_ok());
+    assert!(!path.exists());
+    let body = json!({ "token": token, "name": "alice" });
+    assert_eq!(server.send_json("POST", "/api/setup", body).await.0, 401);
+    assert_eq!(role(&server, "alice").await, "admin");
+}
+
+#[async_std::test]
+async fn no_setup_token_with_an_owner() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+
+    assert!(!server.data_dir().join("setup_token").exists());
+    let body = json!({ "token": "", "name": "mia" });
+    assert_eq!(server.send_json("POST", "/api/setup", body).await.0, 401);
+}
+
+#[async_std::test]
+async fn changing_roles() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+
+    let (status, reply) = set_role(&server, "mia", "mia", "admin").await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "not_admin");
+    assert_eq!(set_role(&server, "alice", "mia", "admin").await.0, 200);
+    assert_eq!(set_role(&server, "mia", "alice", "guest").await.0, 200);
+    assert_eq!(role(&server, "alice").await, "guest");
+    assert_eq!(set_role(&server, "mia", "nobody", "child").await.0, 404);
+
+    // Admins can't touch the owner
+    let (status, reply) = set_role(&server, "mia", "olive", "member").await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "not_owner");
+    assert_eq!(set_role(&server, "mia", "mia", "owner").await.0, 403);
+}
+
+#[async_std::test]
+async fn handing_over_ownership() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+
+    let (status, reply) = set_role(&server, "olive", "olive", "admin").await;
+    assert_eq!(status, 409);
+    assert_eq!(reply["reason"], "is_owner");
+
+    // Not to someone who can't log in
+    assert!(server.post("f olive\\alice").await.is_ok());
+    let (status, reply) = set_role(&server, "olive", "alice", "owner").await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "deactivated");
+    assert_eq!(role(&server, "olive").await, "owner");
+
+    assert_eq!(set_role(&server, "olive", "mia", "owner").await.0, 200);
+    assert_eq!(role(&server, "mia").await, "owner");
+    assert_eq!(role(&server, "olive").await, "admin");
+    assert_eq!(set_role(&server, "olive", "mia", "member").await.0, 403);
+}
+
+#[async_std::test]
+async fn last_admin() {
+    let server = TestServer::with_data(
+        roles_data(&[("alice", 1, Role::Admin), ("mia", 1, Role::Member)]),
+        |_| {},
+    );
+
+    let (status, reply) = set_role(&server, "alice", "alice", "member").await;
+    assert_eq!(status, 409);
+    assert_eq!(reply["reason"], "last_admin");
+    let body = json!({ "user": "alice" });
+    let (status, _) =
+        server.send_json("DELETE", "/api/people/alice", body).await;
+    assert_eq!(status, 409);
+
+    // Fine once there's another
+    assert_eq!(set_role(&server, "alice", "mia", "admin").await.0, 200);
+    assert_eq!(set_role(&server, "alice", "alice", "member").await.0, 200);
+}
+
+#[async_std::test]
+async fn renaming() {
+    for storage in [Backend::Journal, Backend::Muon, Backend::Sqlite] {
+        let server =
+            household_with_tacos(&ROLES, |config| config.storage = storage)
+                .await;
+        assert!(server.vote("mia", "0").await.is_ok());
+        assert!(server.rate("mia", "0", 4, None).await.is_ok());
+
+        let server = &server;
+        let rename = |user: &str, name: &str, new_name: &str| {
+            let body = json!({ "user": user, "name": new_name });
+            let path = format!("/api/people/{name}");
+            async move { server.send_json("PATCH", &path, body).await.0 }
+        };
+        assert_eq!(rename("mia", "alice", "al").await, 403);
+        assert_eq!(rename("alice", "mia", "olive").await, 409);
+        assert_eq!(rename("alice", "mia", "").await, 422);
+        assert_eq!(rename("alice", "olive", "o").await, 403);
+        let code = server.invite("olive", json!({})).await;
+        assert_eq!(rename("alice", "mia", "mimi").await, 200);
+        assert_eq!(rename("olive", "olive", "liv").await, 200);
+
+        let data = server.reload();
+        assert!(!data.people.contains_key("mia"), "{:?}", storage);
+        assert_eq!(data.people["mimi"].role, Role::Member);
+        assert_eq!(data.people["liv"].role, Role::Owner);
+        let tacos = &data.dinners[&0];
+        assert_eq!(tacos.vote.as_deref(), Some("mimi"), "{:?}", storage);
+        assert_eq!(tacos.ratings[0].person, "mimi");
+        assert!(data.history.iter().all(|event| event.person != "mia"));
+        assert_eq!(data.invites[&code].by, "liv", "{:?}", storage);
+        assert!(server.unvote("mimi", "0").await.is_ok());
+    }
+}
+
+#[async_std::test]
+async fn removing() {
+    for storage in [Backend::Journal, Backend::Muon, Backend::Sqlite] {
+        let server =
+            household_with_tacos(&ROLES, |config| config.storage = storage)
+                .await;
+        assert!(server.vote("mia", "0").await.is_ok());
+        assert!(server.rate("mia", "0", 4, None).await.is_ok());
+
+        let server = &server;
+        let remove = |user: &str, name: &str| {
+            let body = json!({ "user": user });
+            let path = format!("/api/people/{name}");
+            async move { server.send_json("DELETE", &path, body).await.0 }
+        };
+        assert_eq!(remove("mia", "alice").await, 403);
+        assert_eq!(remove("alice", "olive").await, 403);
+        assert_eq!(remove("olive", "olive").await, 409);
+        assert_eq!(remove("alice", "mia").await, 200);
+        assert_eq!(remove("alice", "mia").await, 404);
+
+        let data = server.reload();
+        assert!(!data.people.contains_key("mia"), "{:?}", storage);
+        assert_eq!(data.dinners[&0].vote, None, "{:?}", storage);
+        assert!(data.dinners[&0].ratings.is_empty(), "{:?}", storage);
+        assert_eq!(server.get_votes("mia").await.body, "");
+    }
+}
+
+#[async_std::test]
+async fn sessions_follow_people() {
+    let mut data =
+        roles_data(&[("olive", 1, Role::Owner), ("mia", 1, Role::Member)]);
+    for (name, password) in [("olive", "olive's secret"), ("mia", "1357")] {
+        data.people.get_mut(name).unwrap().password =
+            Some(auth::hash(password).unwrap());
+    }
+    let server = TestServer::with_data(data, |config| {
+        config.legacy_unauthenticated = false;
+    });
+    server.log_in("mia", "1357").await;
+    let mia = server.token();
+    server.log_in("olive", "olive's secret").await;
+    let olive = server.token();
+    assert!(server.new_dinner("olive", "Tacos").await.is_ok());
+
+    let body = json!({ "user": "olive", "name": "mimi" });
+    let (status, _) = server.send_json("PATCH", "/api/people/mia", body).await;
+    assert_eq!(status, 200);
+    server.set_token(mia.as_deref());
+    assert!(server.vote("mimi", "0").await.is_ok());
+
+    // And end with them
+    server.set_token(olive.as_deref());
+    let body = json!({ "user": "olive" });
+    let (status, _) =
+        server.send_json("DELETE", "/api/people/mimi", body).await;
+    assert_eq!(status, 200);
+    server.set_token(mia.as_deref());
+    assert_eq!(server.new_dinner("mimi", "Pizza").await.status, 401);
+}
+
+#[async_std::test]
+async fn renaming_and_leaving_yourself() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.rate("mia", "0", 4, None).await.is_ok());
+
+    let reply = server.post("i mia\\alice\\al").await;
+    assert_eq!(reply.status, 403);
+    assert!(server.post("i mia\\mia\\mimi").await.is_ok());
+    assert_eq!(server.list().await[0], ["Tacos", "-", "mimi"]);
+    assert_eq!(server.get_votes("mia").await.body, "");
+
+    assert_eq!(server.post("x mimi\\alice").await.status, 403);
+    assert!(server.post("x mimi\\mimi").await.is_ok());
+    assert_eq!(server.list().await[0], ["Tacos", "-"]);
+    let (_, tacos) = server.get_json("/api/dinners/0").await;
+    assert_eq!(tacos["ratings"]["count"], 0);
+    assert_eq!(server.get_votes("mimi").await.body, "");
+
+    // Except the owner, who has to hand over first
+    let reply = server.post("x olive\\olive").await;
+    assert_eq!(
+        reply.body,
+        "rejected: the owner has to hand over ownership first"
+    );
+}
+
+#[async_std::test]
+async fn deactivating() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.rate("mia", "0", 4, None).await.is_ok());
+
+    let user = |user: &str| json!({ "user": user });
+    let (status, _) = server
+        .send_json("POST", "/api/people/alice/deactivate", user("mia"))
+        .await;
+    assert_eq!(status, 403);
+    let (status, _) = server
+        .send_json("POST", "/api/people/olive/deactivate", user("alice"))
+        .await;
+    assert_eq!(status, 403);
+    let (status, _) = server
+        .send_json("POST", "/api/people/mia/deactivate", user("alice"))
+        .await;
+    assert_eq!(status, 200);
+
+    // Their vote is given back, and their rating stays
+    assert_eq!(server.list().await[0], ["Tacos", "-"]);
+    assert_eq!(server.get_votes("mia").await.body, "1\\FALSE");
+    let (_, tacos) = server.get_json("/api/dinners/0").await;
+    assert_eq!(tacos["ratings"]["count"], 1);
+    let (_, mia) = server.get_json("/api/people/mia").await;
+    assert_eq!(mia["deactivated"], true);
+    let reply = server.vote("mia", "0").await;
+    assert_eq!(reply.status, 403);
+    assert_eq!(reply.body, "rejected: that account is deactivated");
+
+    assert!(server.post("w alice\\mia").await.is_ok());
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.post("f olive\\mia").await.is_ok());
+    assert_eq!(server.post("f olive\\olive").await.status, 409);
+}
+
+#[async_std::test]
+async fn deactivating_the_last_admin() {
+    let server = TestServer::with_data(
+        roles_data(&[("alice", 1, Role::Admin), ("bob", 1, Role::Admin)]),
+        |_| {},
+    );
+
+    assert!(server.post("f alice\\bob").await.is_ok());
+    let reply = server.post("f alice\\alice").await;
+    assert_eq!(reply.body, "rejected: there has to be an admin left");
+    // bob doesn't count, so can stop being one
+    let body = json!({ "user": "alice", "role": "member" });
+    let (status, _) =
+        server.send_json("PUT", "/api/people/bob/role", body).await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send_json(
+            "PUT",
+            "/api/people/alice/role",
+            json!({
+                "user": "alice",
+                "role": "member",
+            }),
+        )
+        .await;
+    assert_eq!(status, 409);
+}
+
+#[async_std::test]
+async fn deactivated_people_cant_log_in() {
+    let mut data =
+        roles_data(&[("olive", 1, Role::Owner), ("mia", 1, Role::Member)]);
+    for (name, password) in [("olive", "olive's secret"), ("mia", "1357")] {
+        data.people.get_mut(name).unwrap().password =
+            Some(auth::hash(password).unwrap());
+    }
+    let server = TestServer::with_data(data, |config| {
+        config.legacy_unauthenticated = false;
+    });
+    server.log_in("mia", "1357").await;
+    let mia = server.token();
+    server.log_in("olive", "olive's secret").await;
+    assert!(server.post("f olive\\mia").await.is_ok());
+
+    server.set_token(mia.as_deref());
+    assert_eq!(server.new_dinner("mia", "Tacos").await.status, 401);
+    let reply = server.log_in("mia", "1357").await;
+    assert_eq!(reply.status, 403);
+    assert_eq!(server.log_in("mia", "wrong").await.status, 401);
+
+    server.log_in("olive", "olive's secret").await;
+    assert!(server.post("w olive\\mia").await.is_ok());
+    assert!(server.log_in("mia", "1357").await.is_ok());
+}
+
+#[async_std::test]
+async fn deactivation_is_saved() {
+    for storage in [Backend::Journal, Backend::Muon, Backend::Sqlite] {
+        let server =
+            household_with_tacos(&ROLES, |config| config.storage = storage)
+                .await;
+        assert!(server.vote("mia", "0").await.is_ok());
+        assert!(server.post("f alice\\mia").await.is_ok());
+
+        let data = server.reload();
+        assert!(data.people["mia"].deactivated, "{:?}", storage);
+        assert!(!data.people["alice"].deactivated, "{:?}", storage);
+        assert_eq!(data.people["mia"].votes, 1, "{:?}", storage);
+        assert_eq!(data.dinners[&0].vote, None, "{:?}", storage);
+    }
+}
@@ -0,0 +1,121 @@
+// Dinner photos: what's accepted, and serving them with an ETag.
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{roles_data, TestServer};
+use tide_server::{apply_event, roles::Role, DbEvent};
+
+// Largest photo accepted, in bytes
+const MAX_PHOTO_BYTES: usize = 4 * 1024 * 1024;
+// Start of a PNG file
+const PNG: &[u8] = b"\x89PNG\r\n\x1A\n";
+
+// A server where admin alice can edit Tacos (ID 0)
+async fn kitchen() -> TestServer {
+    let server = TestServer::with_people(&[("alice", 1, true)]);
+    assert!(server.new_dinner("alice", "Tacos").await.is_ok());
+    server
+}
+
+fn png(len: usize) -> Vec<u8> {
+    let mut photo = PNG.to_vec();
+    photo.resize(len, 7);
+    photo
+}
+
+async fn upload(server: &TestServer, mime: &str, photo: &[u8]) -> u16 {
+    let path = "/api/dinners/0/photo?user=alice";
+    let headers = [("Content-Type", mime)];
+    server.send("PUT", path, &headers, photo).await.status
+}
+
+#[async_std::test]
+async fn size_cap() {
+    let server = kitchen().await;
+
+    assert_eq!(
+        upload(&server, "image/png", &png(MAX_PHOTO_BYTES)).await,
+        200
+    );
+    let too_big = png(MAX_PHOTO_BYTES + 1);
+    assert_eq!(upload(&server, "image/png", &too_big).await, 413);
+
+    // The one that fit is still there
+    let reply = server.send("GET", "/api/dinners/0/photo", &[], &[]).await;
+    assert_eq!(reply.body.len(), MAX_PHOTO_BYTES);
+}
+
+#[async_std::test]
+async fn sniffing() {
+    let server = kitchen().await;
+
+    // Whatever it's declared as, it has to look like an image we accept
+    assert_eq!(upload(&server, "image/png", b"not a picture").await, 415);
+    assert_eq!(upload(&server, "text/plain", &png(64)).await, 415);
+    let bmp = b"BM\x00\x00\x00\x00\x00\x00\x00\x00";
+    assert_eq!(upload(&server, "image/bmp", bmp).await, 415);
+    let photo = server.send("GET", "/api/dinners/0/photo", &[], &[]).await;
+    assert_eq!(photo.status, 404);
+
+    // It's served as what it is, not what it was declared as
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend([0; 16]);
+    assert_eq!(upload(&server, "image/png", &gif).await, 200);
+    let photo = server.send("GET", "/api/dinners/0/photo", &[], &[]).await;
+    assert_eq!(photo.status, 200);
+    assert_eq!(photo.header("content-type"), Some("image/gif"));
+    assert_eq!(photo.body, gif);
+}
+
+#[async_std::test]
+async fn etags() {
+    let server = kitchen().await;
+    assert_eq!(upload(&server, "image/png", &png(64)).await, 200);
+
+    let path = "/api/dinners/0/photo";
+    let photo = server.send("GET", path, &[], &[]).await;
+    let etag = photo.header("etag").unwrap().to_string();
+    assert_eq!(photo.header("cache-control"), Some("no-cache"));
+
+    let cached = [("If-None-Match", etag.as_str())];
+    let reply = server.send("GET", path, &cached, &[]).await;
+    assert_eq!(reply.status, 304);
+    assert!(reply.body.is_empty());
+    assert_eq!(reply.header("etag"), Some(etag.as_str()));
+
+    // A new photo gets a new tag, so the cached one is sent again
+    assert_eq!(upload(&server, "image/png", &png(65)).await, 200);
+    let reply = server.send("GET", path, &cached, &[]).await;
+    assert_eq!(reply.status, 200);
+    assert_ne!(reply.header("etag"), Some(etag.as_str()));
+    assert_eq!(reply.body, png(65));
+}
+
+// Every change is made to a copy of the database, which shouldn't copy the
+// photos
+#[test]
+fn copies_share_photos() {
+    let mut data = roles_data(&[("alice", 1, Role::Admin)]);
+    let events = [
+        DbEvent::NewDinner {
+            user: "alice".into(),
+            name: "Tacos".into(),
+        },
+        DbEvent::EditPhoto {
+            user: "alice".into(),
+            index: "0".into(),
+            photo: Some(png(64).into()),
+        },
+    ];
+    for event in events {
+        assert!(apply_event(&mut data, event, 1).is_applied());
+    }
+
+    let copy = data.clone();
+    let photo = |data: &tide_server::DatabaseData| {
+        data.dinners[&0].photo.clone().unwrap()
+    };
+    assert!(Arc::ptr_eq(&photo(&data), &photo(&copy)));
+}
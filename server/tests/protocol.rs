@@ -0,0 +1,162 @@
+// Random messages, to make sure the legacy protocol copes with anything.
+
+mod common;
+
+use async_std::task::block_on;
+use common::{Reply, TestServer};
+use proptest::{
+    prelude::*,
+    test_runner::{Config, TestRunner},
+};
+use tide_server::protocol::Command;
+
+// An argument, any text without the separator
+fn arg() -> impl Strategy<Value = String> {
+    "[^\\\\]{1,12}"
+}
+
+// Names people use, to make sure some messages do something
+fn name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("alice".to_string()),
+        Just("Zoë".to_string()),
+        Just("🍕 fan".to_string()),
+        Just("Crème brûlée".to_string()),
+        Just("0".to_string()),
+        arg(),
+    ]
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    let user_index = || (name(), name());
+    let user_index_name = || (name(), name(), name());
+    prop_oneof![
+        Just(Command::List),
+        name().prop_map(|index| Command::Get { index }),
+        user_index().prop_map(|(user, index)| Command::Vote { user, index }),
+        user_index().prop_map(|(user, index)| Command::Unvote { user, index }),
+        name().prop_map(|user| Command::ViewVotes { user }),
+        (name(), proptest::option::of(arg()))
+            .prop_map(|(name, invite)| Command::NewUser { name, invite }),
+        user_index().prop_map(|(user, name)| Command::NewDinner { user, name }),
+        user_index_name().prop_map(|(user, index, name)| {
+            Command::EditShortname { user, index, name }
+        }),
+        user_index_name().prop_map(|(user, index, name)| {
+            Command::EditLongname { user, index, name }
+        }),
+        user_index_name().prop_map(|(user, index, name)| {
+            Command::EditDetails { user, index, name }
+        }),
+        user_index()
+            .prop_map(|(user, index)| Command::DeleteDinner { user, index }),
+        (name(), name(), any::<u8>(), proptest::option::of(arg()))
+            .prop_filter("0 stars and no note clears", |(.., stars, note)| {
+                *stars != 0 || note.is_some()
+            })
+            .prop_map(|(user, index, stars, note)| Command::SetRating {
+                user,
+                index,
+                stars,
+                note,
+            }),
+        user_index()
+            .prop_map(|(user, index)| Command::ClearRating { user, index }),
+        (name(), proptest::option::of(name()))
+            .prop_map(|(user, index)| Command::ViewAnalytics { user, index }),
+        user_index().prop_map(|(user, index)| Command::Serve { user, index }),
+        name().prop_map(|user| Command::GetVotes { user }),
+        (name(), any::<u16>())
+            .prop_map(|(user, votes)| Command::SetVotes { user, votes }),
+        user_index_name().prop_map(|(user, name, new_name)| {
+            Command::RenamePerson {
+                user,
+                name,
+                new_name,
+            }
+        }),
+        user_index()
+            .prop_map(|(user, name)| Command::RemovePerson { user, name }),
+        user_index()
+            .prop_map(|(user, name)| Command::Deactivate { user, name }),
+        user_index()
+            .prop_map(|(user, name)| Command::Reactivate { user, name }),
+    ]
+}
+
+// A message body: made up of commands, or close to one, or anything at all
+fn body() -> impl Strategy<Value = String> {
+    prop_oneof![
+        command().prop_map(|command| command.to_string()),
+        (
+            "[lgvuacnstmdryehzpixfwé🍕 ]",
+            proptest::collection::vec(name(), 0..4),
+        )
+            .prop_map(|(letter, args)| format!("{letter} {}", args.join("\\"))),
+        "[lgvuacnstmdryehz]?.{0,3}",
+        any::<String>(),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn parse_never_panics(message in any::<String>()) {
+        let _ = Command::parse(&message);
+    }
+
+    #[test]
+    fn parse_reads_what_display_writes(command in command()) {
+        prop_assert_eq!(Command::parse(&command.to_string()), Ok(command));
+    }
+
+    // The last argument is the rest of the message
+    #[test]
+    fn last_argument_keeps_backslashes(user in arg(), name in ".{1,20}") {
+        let message = format!("n {user}\\{name}");
+        prop_assert_eq!(
+            Command::parse(&message),
+            Ok(Command::NewDinner { user, name }),
+        );
+    }
+}
+
+fn check(reply: &Reply, body: &[u8]) -> Result<(), TestCaseError> {
+    prop_assert!(
+        reply.status < 500,
+        "{:?} got {:?}",
+        String::from_utf8_lossy(body),
+        reply,
+    );
+    Ok(())
+}
+
+#[test]
+fn server_survives_random_messages() {
+    let server = TestServer::with_people(&[
+        ("alice", 1, true),
+        ("Zoë", 3, false),
+        ("🍕 fan", 3, false),
+    ]);
+    let mut runner = TestRunner::new(Config {
+        cases: 512,
+        ..Config::default()
+    });
+
+    runner
+        .run(&body(), |body| {
+            let reply = block_on(server.post(&body));
+            check(&reply, body.as_bytes())
+        })
+        .unwrap();
+    runner
+        .run(&proptest::collection::vec(any::<u8>(), 0..32), |body| {
+            let reply =
+                block_on(server.request("POST", "/meal_vote", None, &body));
+            check(&reply, &body)
+        })
+        .unwrap();
+
+    // And it still answers
+    assert!(block_on(server.post("l")).is_ok());
+    assert_eq!(block_on(server.get_votes("alice")).status, 200);
+}
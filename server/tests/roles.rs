@@ -0,0 +1,207 @@
+// What each role may do, and roles being kept and upgraded from `admin`.
+
+mod common;
+
+use common::{household, household_with_tacos, roles_data, TestServer};
+use tide_server::{roles::Role, storage::Backend};
+
+// Everyone, so Tacos (ID 0) is added by the owner
+const EVERYONE: [Role; 5] = [
+    Role::Owner,
+    Role::Admin,
+    Role::Member,
+    Role::Child,
+    Role::Guest,
+];
+
+#[async_std::test]
+async fn adding_dinners() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+
+    assert!(server.new_dinner("alice", "Pizza").await.is_ok());
+    assert!(server.new_dinner("mia", "Soup").await.is_ok());
+    for name in ["kid", "gus"] {
+        let reply = server.new_dinner(name, "Cake").await;
+        assert_eq!(reply.status, 403);
+        assert_eq!(reply.body, "rejected: your role can't do that");
+    }
+
+    // But only admins change them
+    let reply = server.edit_details("mia", "Soup", "Hot").await;
+    assert_eq!(reply.body, "rejected: only admins can do that");
+    assert!(server.edit_details("olive", "Soup", "Hot").await.is_ok());
+    assert!(server.delete_dinner("alice", "Soup").await.is_ok());
+}
+
+#[async_std::test]
+async fn voting() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+    assert!(server.new_dinner("olive", "Pizza").await.is_ok());
+
+    // Owners and admins vote for free
+    for name in ["olive", "alice"] {
+        assert!(server.vote(name, "Tacos").await.is_ok());
+        assert!(server.unvote(name, "Tacos").await.is_ok());
+        assert!(server.vote(name, "Tacos").await.is_ok());
+        assert_eq!(server.get_votes(name).await.body, "1\\TRUE");
+        assert!(server.unvote(name, "Tacos").await.is_ok());
+    }
+    for name in ["mia", "kid", "gus"] {
+        assert!(server.vote(name, "Tacos").await.is_ok());
+        assert_eq!(server.get_votes(name).await.body, "0\\FALSE");
+        let reply = server.vote(name, "Pizza").await;
+        assert_eq!(reply.body, "rejected: no votes left");
+
+        if name != "mia" {
+            let reply = server.unvote("mia", "Tacos").await;
+            assert_eq!(reply.body, "rejected: vote belongs to someone else");
+        }
+        assert!(server.unvote(name, "Tacos").await.is_ok());
+        assert_eq!(server.get_votes(name).await.body, "1\\FALSE");
+    }
+    assert!(server.vote("gus", "Tacos").await.is_ok());
+    assert!(server.unvote("olive", "Tacos").await.is_ok());
+    assert_eq!(server.get_votes("gus").await.body, "1\\FALSE");
+}
+
+#[async_std::test]
+async fn rating() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+
+    for name in ["olive", "alice", "mia", "kid"] {
+        assert!(server.rate(name, "Tacos", 4, None).await.is_ok());
+    }
+    let reply = server.rate("gus", "Tacos", 5, None).await;
+    assert_eq!(reply.status, 403);
+    assert_eq!(reply.body, "rejected: your role can't do that");
+    assert_eq!(server.rate("gus", "Tacos", 0, None).await.status, 403);
+}
+
+#[async_std::test]
+async fn guests_keep_their_votes() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+
+    assert!(server.set_votes("alice", 3).await.is_ok());
+    assert_eq!(server.get_votes("kid").await.body, "3\\FALSE");
+    assert_eq!(server.get_votes("gus").await.body, "1\\FALSE");
+}
+
+#[async_std::test]
+async fn seeing_everyones_votes() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+
+    for (name, everyone) in [
+        ("olive", true),
+        ("alice", true),
+        ("mia", false),
+        ("kid", false),
+    ] {
+        let (status, votes) =
+            server.get_json(&format!("/api/people/{name}/votes")).await;
+        assert_eq!(status, 200);
+        assert_eq!(votes["everyone"].is_array(), everyone, "{}", name);
+    }
+
+    let (_, person) = server.get_json("/api/people/olive").await;
+    assert_eq!(person["role"], "owner");
+    assert_eq!(person["admin"], true);
+    let (_, person) = server.get_json("/api/people/kid").await;
+    assert_eq!(person["role"], "child");
+    assert_eq!(person["admin"], false);
+}
+
+#[async_std::test]
+async fn everyones_votes_need_a_login() {
+    let server = household(&[Role::Owner, Role::Member], |config| {
+        config.legacy_unauthenticated = false
+    });
+    assert!(server.log_in("olive", "olive's secret").await.is_ok());
+    assert!(server.new_dinner("olive", "Tacos").await.is_ok());
+    assert!(server.vote("olive", "0").await.is_ok());
+    let token = server.token();
+    server.set_token(None);
+
+    // Without a session, naming the owner only gets the owner's votes
+    let (status, votes) = server.get_json("/api/people/olive/votes").await;
+    assert_eq!(status, 200);
+    assert_eq!(votes["dinners"], serde_json::json!(["Tacos"]));
+    assert!(votes["everyone"].is_null(), "{}", votes);
+    assert_eq!(server.view_votes("olive").await.body, "1\\Tacos");
+
+    server.set_token(token.as_deref());
+    let (_, votes) = server.get_json("/api/people/olive/votes").await;
+    assert_eq!(votes["everyone"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        server.view_votes("olive").await.body,
+        "1\\Tacos\nmia\\1\nolive\\1\\Tacos"
+    );
+}
+
+#[async_std::test]
+async fn only_the_owner_sets_the_owners_password() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+    let secret = |user: &str| serde_json::json!({ "user": user, "password": "a new secret" });
+
+    let (status, reply) = server
+        .send_json("PUT", "/api/people/olive/password", secret("alice"))
+        .await;
+    assert_eq!(status, 403);
+    assert_eq!(reply["reason"], "not_owner");
+    assert!(!server.log_in("olive", "a new secret").await.is_ok());
+    assert!(server.log_in("olive", "olive's secret").await.is_ok());
+
+    // Other people's are still up to admins
+    let (status, _) = server
+        .send_json("PUT", "/api/people/mia/password", secret("alice"))
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send_json("PUT", "/api/people/olive/password", secret("olive"))
+        .await;
+    assert_eq!(status, 200);
+}
+
+#[async_std::test]
+async fn new_people_are_members() {
+    let server = household_with_tacos(&EVERYONE, |_| {}).await;
+    assert!(server.new_user("bob").await.is_ok());
+
+    let (_, person) = server.get_json("/api/people/bob").await;
+    assert_eq!(person["role"], "member");
+}
+
+#[async_std::test]
+async fn roles_are_saved() {
+    for storage in [Backend::Journal, Backend::Muon, Backend::Sqlite] {
+        let server = TestServer::with_data(
+            roles_data(&[("olive", 1, Role::Owner), ("kid", 1, Role::Child)]),
+            |config| config.storage = storage,
+        );
+        assert!(server.new_user("bob").await.is_ok());
+
+        let people = server.reload().people;
+        assert_eq!(people["olive"].role, Role::Owner, "{:?}", storage);
+        assert_eq!(people["kid"].role, Role::Child, "{:?}", storage);
+        assert_eq!(people["bob"].role, Role::Member, "{:?}", storage);
+    }
+}
+
+// From before roles, only `admin` was saved
+#[async_std::test]
+async fn admins_stay_admins() {
+    let server = TestServer::with_config(|config| {
+        config.storage = Backend::Muon;
+    });
+    std::fs::write(
+        server.data_dir().join("database"),
+        "people: alice\n  value: 2\n    admin: true\n\
+            people: bob\n  value: 1\n    admin: false\n\
+            next_dinner: 0\nversion: 2\n",
+    )
+    .unwrap();
+
+    let people = server.reload().people;
+    assert_eq!(people["alice"].role, Role::Admin);
+    assert_eq!(people["alice"].votes, 2);
+    assert_eq!(people["bob"].role, Role::Member);
+}
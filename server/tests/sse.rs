@@ -0,0 +1,29 @@
+// Notifications sent through server sent events at `/meal_vote/sse`.
+
+mod common;
+
+use common::TestServer;
+
+#[async_std::test]
+async fn notifies_on_connect() {
+    let server = TestServer::start();
+    let mut events = server.sse().await;
+
+    let event = events.next().await;
+    assert_eq!(event, Some(("notify".into(), "Time to vote!".into())));
+    assert_eq!(events.next().await, None);
+}
+
+#[async_std::test]
+async fn notifies_on_schedule() {
+    let server = TestServer::with_config(|config| {
+        config.notify_every = 1;
+        config.notify_message = "Dinner!".into();
+    });
+    let mut events = server.sse().await;
+
+    for _ in 0..2 {
+        let event = events.next().await;
+        assert_eq!(event, Some(("notify".into(), "Dinner!".into())));
+    }
+}
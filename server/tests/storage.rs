@@ -0,0 +1,239 @@
+// Saving the database: replaying and compacting the journal, and recovering
+// from corrupt files and failed writes.
+
+mod common;
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use common::TestServer;
+use serde_json::json;
+use tempfile::TempDir;
+use tide_server::{
+    apply_event,
+    storage::{Backend, Storage},
+    DatabaseData, DbEvent,
+};
+
+// Changes between snapshots of the journal
+const COMPACT_EVERY: usize = 1000;
+
+// Apply `event` to `data` and save it, the way the database thread does.
+fn save(storage: &mut dyn Storage, data: &mut DatabaseData, event: DbEvent) {
+    let outcome = apply_event(data, event.clone(), 1);
+    assert!(outcome.is_applied(), "{}", outcome);
+    storage.apply(&event, 1, data).unwrap();
+}
+
+fn new_dinner(name: &str) -> DbEvent {
+    DbEvent::NewDinner {
+        user: "alice".into(),
+        name: name.into(),
+    }
+}
+
+// alice (the owner, as the first person), with her vote on Tacos
+fn start(storage: &mut dyn Storage) -> DatabaseData {
+    let mut data = DatabaseData::default();
+    let new_user = DbEvent::NewUser {
+        name: "alice".into(),
+        votes: 1,
+        password: None,
+        invite: None,
+        invite_needed: true,
+    };
+    save(storage, &mut data, new_user);
+    save(storage, &mut data, new_dinner("Tacos"));
+    let vote = DbEvent::Vote {
+        user: "alice".into(),
+        index: "0".into(),
+    };
+    save(storage, &mut data, vote);
+    data
+}
+
+fn load(dir: &Path) -> DatabaseData {
+    Backend::Journal.open(dir).unwrap().load().unwrap().unwrap()
+}
+
+fn lines(path: &Path) -> usize {
+    std::fs::read_to_string(path).unwrap().lines().count()
+}
+
+// Files in `dir` whose names start with `prefix`.
+fn files(dir: &Path, prefix: &str) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with(prefix))
+        .collect()
+}
+
+#[test]
+fn replay() {
+    let dir = TempDir::new().unwrap();
+    let mut storage = Backend::Journal.open(dir.path()).unwrap();
+    assert!(storage.load().unwrap().is_none());
+    start(&mut *storage);
+
+    // Nothing's compacted yet, it's all in the journal
+    assert!(!dir.path().join("database").exists());
+    assert_eq!(lines(&dir.path().join("journal")), 3);
+    let data = load(dir.path());
+    assert_eq!(data.people["alice"].votes, 1);
+    assert_eq!(data.dinners[&0].name, "Tacos");
+    assert_eq!(data.dinners[&0].vote.as_deref(), Some("alice"));
+    assert_eq!(data.history.len(), 1);
+    assert_eq!(data.next_dinner, 1);
+}
+
+#[test]
+fn torn_tail() {
+    let dir = TempDir::new().unwrap();
+    let journal = dir.path().join("journal");
+    let mut storage = Backend::Journal.open(dir.path()).unwrap();
+    start(&mut *storage);
+    let len = std::fs::metadata(&journal).unwrap().len();
+
+    // A crash in the middle of writing the next entry
+    let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+    file.write_all(br#"{"seq":4,"at":1,"event":{"type":"new_d"#)
+        .unwrap();
+    drop(file);
+
+    let mut storage = Backend::Journal.open(dir.path()).unwrap();
+    let mut data = storage.load().unwrap().unwrap();
+    assert_eq!(data.dinners.len(), 1);
+    assert_eq!(std::fs::metadata(&journal).unwrap().len(), len);
+
+    // Changes after it are kept
+    save(&mut *storage, &mut data, new_dinner("Soup"));
+    let data = load(dir.path());
+    assert_eq!(data.dinners[&1].name, "Soup");
+    assert_eq!(lines(&journal), 4);
+}
+
+#[test]
+fn compaction() {
+    let dir = TempDir::new().unwrap();
+    let journal = dir.path().join("journal");
+    let mut storage = Backend::Journal.open(dir.path()).unwrap();
+    let mut data = start(&mut *storage);
+
+    for i in 3..COMPACT_EVERY {
+        save(&mut *storage, &mut data, new_dinner(&format!("Dinner {i}")));
+    }
+    assert!(dir.path().join("database").exists());
+    assert_eq!(lines(&journal), 0);
+    save(&mut *storage, &mut data, new_dinner("Soup"));
+    assert_eq!(lines(&journal), 1);
+
+    let loaded = load(dir.path());
+    assert_eq!(loaded.dinners.len(), COMPACT_EVERY - 1);
+    assert_eq!(loaded.dinners[&0].vote.as_deref(), Some("alice"));
+    assert_eq!(loaded.dinners[&998].name, "Soup");
+    assert_eq!(loaded.next_dinner, 999);
+
+    // Entries the snapshot already has aren't applied twice, in case
+    // compacting stopped before the journal was emptied
+    let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+    let old = json!({
+        "seq": 4,
+        "at": 1,
+        "event": { "type": "new_dinner", "user": "alice", "name": "Again" },
+    });
+    writeln!(file, "{}", old).unwrap();
+    drop(file);
+    assert_eq!(load(dir.path()).dinners.len(), COMPACT_EVERY - 1);
+}
+
+#[test]
+fn corrupt_file_falls_back_to_the_backup() {
+    let dir = TempDir::new().unwrap();
+    let database = dir.path().join("database");
+    let mut storage = Backend::Muon.open(dir.path()).unwrap();
+    let mut data = start(&mut *storage);
+    save(&mut *storage, &mut data, new_dinner("Soup"));
+    std::fs::write(&database, b"not muon").unwrap();
+
+    // The file from before the last change is used
+    let data = storage.load().unwrap().unwrap();
+    assert_eq!(data.dinners.len(), 1);
+    assert_eq!(data.dinners[&0].vote.as_deref(), Some("alice"));
+    let corrupt = files(dir.path(), "database.corrupt-");
+    assert_eq!(corrupt.len(), 1);
+    let quarantined = std::fs::read(dir.path().join(&corrupt[0])).unwrap();
+    assert_eq!(quarantined, b"not muon");
+
+    // And put back, so the next start doesn't need it
+    let mut storage = Backend::Muon.open(dir.path()).unwrap();
+    assert_eq!(storage.load().unwrap().unwrap().dinners.len(), 1);
+    assert_eq!(files(dir.path(), "database.corrupt-").len(), 1);
+}
+
+#[test]
+fn corrupt_file_without_a_backup() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("database"), b"not muon").unwrap();
+
+    let Err(error) = Backend::Muon.open(dir.path()).unwrap().load() else {
+        panic!("a corrupt file loaded");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(files(dir.path(), "database.corrupt-").len(), 1);
+}
+
+#[test]
+fn corrupt_journal_entry() {
+    let dir = TempDir::new().unwrap();
+    let journal = dir.path().join("journal");
+    let mut storage = Backend::Journal.open(dir.path()).unwrap();
+    start(&mut *storage);
+    let good = std::fs::read(&journal).unwrap();
+
+    let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+    writeln!(file, "not json").unwrap();
+    let soup = json!({
+        "seq": 4,
+        "at": 1,
+        "event": { "type": "new_dinner", "user": "alice", "name": "Soup" },
+    });
+    writeln!(file, "{}", soup).unwrap();
+    drop(file);
+
+    // Everything from the corrupt entry on is dropped, after copying it
+    let data = load(dir.path());
+    assert_eq!(data.dinners.len(), 1);
+    assert_eq!(std::fs::read(&journal).unwrap(), good);
+    let corrupt = files(dir.path(), "journal.corrupt-");
+    assert_eq!(corrupt.len(), 1);
+    let copy = std::fs::read_to_string(dir.path().join(&corrupt[0])).unwrap();
+    assert!(copy.contains("not json"));
+    assert!(copy.contains("Soup"));
+}
+
+#[async_std::test]
+async fn failed_writes_change_nothing() {
+    let server = TestServer::with_people(&[("alice", 1, true)]);
+    let journal = server.data_dir().join("journal");
+    let dinner = json!({ "user": "alice", "name": "Tacos" });
+
+    // The journal can't be written while it's a directory
+    std::fs::remove_file(&journal).unwrap();
+    std::fs::create_dir(&journal).unwrap();
+    let (status, reply) = server
+        .send_json("POST", "/api/dinners", dinner.clone())
+        .await;
+    assert_eq!(status, 500);
+    assert_eq!(reply["outcome"], "not_saved");
+    assert_eq!(server.get_json("/api/dinners").await.1, json!([]));
+
+    // Once it can be written again, the server carries on
+    std::fs::remove_dir(&journal).unwrap();
+    assert_eq!(
+        server.send_json("POST", "/api/dinners", dinner).await.0,
+        201
+    );
+    let (_, dinners) = server.get_json("/api/dinners").await;
+    assert_eq!(dinners[0]["id"], 0);
+    assert_eq!(server.reload().dinners[&0].name, "Tacos");
+}
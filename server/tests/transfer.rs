@@ -0,0 +1,326 @@
+// Exporting and importing the database as JSON or CSV.
+
+mod common;
+
+use common::{household, household_with_tacos, roles_data, TestServer};
+use serde_json::{json, Value};
+use tide_server::roles::Role;
+
+// Admin alice, who adds Tacos (ID 0), and member mia
+const ROLES: [Role; 2] = [Role::Admin, Role::Member];
+
+async fn import(
+    server: &TestServer,
+    query: &str,
+    mime: &str,
+    file: &str,
+) -> (u16, Value) {
+    let path = format!("/api/admin/import?user=alice{query}");
+    let reply = server
+        .request("POST", &path, Some(mime), file.as_bytes())
+        .await;
+    (reply.status, serde_json::from_str(&reply.body).unwrap())
+}
+
+async fn export(server: &TestServer, query: &str) -> String {
+    let path = format!("/api/admin/export?user=alice{query}");
+    let reply = server.request("GET", &path, None, &[]).await;
+    assert!(reply.is_ok(), "{:?}", reply);
+    reply.body
+}
+
+async fn backups(server: &TestServer) -> usize {
+    let (_, backups) = server.get_json("/api/admin/backups?user=alice").await;
+    backups.as_array().unwrap().len()
+}
+
+#[async_std::test]
+async fn json_is_checked_first() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    let before = export(&server, "").await;
+
+    let file = json!({
+        "people": [{ "name": " " }, { "name": "carol" }],
+        "dinners": [
+            { "name": "Soup", "vote": "dave" },
+            { "name": "Soup" },
+            {
+                "name": "Pie",
+                "photo": "not base64!",
+                "ratings": [{ "person": "carol", "stars": 9, "note": null }],
+            },
+        ],
+    });
+    let (status, reply) =
+        import(&server, "", "application/json", &file.to_string()).await;
+    assert_eq!(status, 422);
+    let errors = reply["errors"].as_array().unwrap();
+    let has = |error: &str| errors.iter().any(|e| e.as_str() == Some(error));
+    assert!(
+        has("person name ` ` is blank or has a backslash or control character"),
+        "{:?}",
+        errors,
+    );
+    assert!(has("dinner `Soup` is there twice"), "{:?}", errors);
+    assert!(
+        has("`dave` voted for `Soup`, but doesn't exist"),
+        "{:?}",
+        errors
+    );
+    assert!(has("photo of `Pie` isn't valid base64"), "{:?}", errors);
+    assert!(
+        has(
+            "rating of `Pie` by `carol` has stars out of range or a note \
+            that's too long"
+        ),
+        "{:?}",
+        errors,
+    );
+
+    let (status, reply) =
+        import(&server, "", "application/json", "{\"people\": 3}").await;
+    assert_eq!(status, 422);
+    assert_eq!(reply["errors"].as_array().unwrap().len(), 1);
+
+    // Nothing was imported, or backed up
+    assert_eq!(export(&server, "").await, before);
+    assert_eq!(backups(&server).await, 0);
+}
+
+#[async_std::test]
+async fn csv_is_checked_first() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    let before = export(&server, "").await;
+
+    for (file, error) in [
+        (
+            "kind,dinner\npudding,Jelly\n",
+            "line 2 has unknown kind `pudding`",
+        ),
+        (
+            "kind,dinner,person\ndinner,Soup,\nperson,,\n",
+            "line 3 has no person",
+        ),
+        (
+            "kind,dinner,person\nrating,Soup,bob\n",
+            "line 2 has no stars",
+        ),
+    ] {
+        let (status, reply) =
+            import(&server, "&format=csv", "text/csv", file).await;
+        assert_eq!(status, 422);
+        assert_eq!(reply["errors"], json!([error]));
+    }
+    let file = "dinner,vote\nSoup,dave\n";
+    let (status, reply) =
+        import(&server, "&format=csv", "text/csv", file).await;
+    assert_eq!(status, 422);
+    assert_eq!(
+        reply["errors"],
+        json!(["`dave` voted for `Soup`, but doesn't exist"]),
+    );
+    assert_eq!(export(&server, "").await, before);
+}
+
+#[async_std::test]
+async fn merging() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.rate("mia", "0", 4, Some("yum")).await.is_ok());
+
+    // Only what's in the file changes
+    let file = "\
+kind,dinner,short,long,person,votes,role,stars
+dinner,Tacos,Crunchy,,,,,
+dinner,Soup,Hot,Tomato,,,,
+person,,,,carol,3,child,
+rating,Soup,,,carol,,,5
+";
+    let (status, reply) =
+        import(&server, "&format=csv", "text/csv", file).await;
+    assert_eq!(status, 200, "{}", reply);
+    assert_eq!(backups(&server).await, 1);
+
+    let data = server.reload();
+    let tacos = &data.dinners[&0];
+    assert_eq!(tacos.short, "Crunchy");
+    assert_eq!(tacos.long, "-");
+    assert_eq!(tacos.vote.as_deref(), Some("mia"));
+    assert_eq!(tacos.ratings.len(), 1);
+    let soup = &data.dinners[&1];
+    assert_eq!((soup.short.as_str(), soup.long.as_str()), ("Hot", "Tomato"));
+    assert_eq!(soup.ratings[0].person, "carol");
+    assert_eq!(data.people.len(), 3);
+    assert_eq!(data.people["carol"].votes, 3);
+    assert_eq!(data.people["mia"].votes, 0);
+
+    // People's votes are left alone too, unless they're in the file
+    assert!(server.set_votes("alice", 5).await.is_ok());
+    let file = json!({ "people": [{ "name": "mia", "role": "child" }] });
+    let (status, reply) =
+        import(&server, "", "application/json", &file.to_string()).await;
+    assert_eq!(status, 200, "{}", reply);
+    let file = "kind,person,role\nperson,carol,guest\n";
+    let (status, reply) =
+        import(&server, "&format=csv", "text/csv", file).await;
+    assert_eq!(status, 200, "{}", reply);
+    let data = server.reload();
+    assert_eq!(data.people["mia"].role, Role::Child);
+    assert_eq!(data.people["mia"].votes, 5);
+    assert_eq!(data.people["carol"].role, Role::Guest);
+    assert_eq!(data.people["carol"].votes, 5);
+}
+
+#[async_std::test]
+async fn replacing() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.rate("mia", "0", 4, Some("yum")).await.is_ok());
+
+    let file = json!({
+        "people": [{ "name": "alice", "votes": 2, "role": "admin" }],
+        "dinners": [{ "name": "Soup", "short": "Hot" }],
+    });
+    let (status, _) = import(
+        &server,
+        "&mode=replace",
+        "application/json",
+        &file.to_string(),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    // Tacos and mia are gone, and so are mia's vote and rating
+    let data = server.reload();
+    assert_eq!(data.people.len(), 1);
+    assert_eq!(data.dinners.len(), 1);
+    assert_eq!(data.dinners[&1].name, "Soup");
+    assert_eq!(data.dinners[&1].vote, None);
+    assert_eq!(backups(&server).await, 1);
+}
+
+#[async_std::test]
+async fn round_trip() {
+    let server = household_with_tacos(&ROLES, |_| {}).await;
+    assert!(server.vote("mia", "0").await.is_ok());
+    assert!(server.rate("mia", "0", 4, Some("yum")).await.is_ok());
+    let csv = export(&server, "&format=csv").await;
+    let json = export(&server, "").await;
+
+    let copy = household(&[Role::Admin], |_| {});
+    let path = "/api/admin/import?user=alice&format=csv&mode=replace";
+    let reply = copy
+        .request("POST", path, Some("text/csv"), csv.as_bytes())
+        .await;
+    assert!(reply.is_ok(), "{:?}", reply);
+    assert_eq!(export(&copy, "").await, json);
+}
+
+#[async_std::test]
+async fn nobody_becomes_the_owner() {
+    let server = household(&[Role::Owner, Role::Admin, Role::Member], |_| {});
+
+    for name in ["alice", "eve"] {
+        let file = json!({ "people": [{ "name": name, "role": "owner" }] });
+        let (status, reply) =
+            import(&server, "", "application/json", &file.to_string()).await;
+        assert_eq!(status, 422);
+        assert_eq!(
+            reply["errors"],
+            json!([format!("`{name}` can't be made the owner by importing")]),
+        );
+    }
+    let data = server.reload();
+    assert_eq!(data.people["olive"].role, Role::Owner);
+    assert_eq!(data.people["alice"].role, Role::Admin);
+    assert!(!data.people.contains_key("eve"));
+
+    // The owner can stay the owner, so an export can be imported again
+    let file = export(&server, "").await;
+    let (status, reply) =
+        import(&server, "&mode=replace", "application/json", &file).await;
+    assert_eq!(status, 200, "{}", reply);
+}
+
+#[async_std::test]
+async fn the_owners_role_cant_change() {
+    let server = household(&[Role::Owner, Role::Admin, Role::Member], |_| {});
+
+    let file = json!({ "people": [{ "name": "olive", "role": "member" }] });
+    let (status, reply) =
+        import(&server, "", "application/json", &file.to_string()).await;
+    assert_eq!(status, 422);
+    assert_eq!(
+        reply["errors"],
+        json!(["`olive` is the owner, so their role can't change"]),
+    );
+    assert_eq!(server.reload().people["olive"].role, Role::Owner);
+
+    // Other roles change the way they could be set
+    let file = json!({ "people": [{ "name": "mia", "role": "admin" }] });
+    let (status, _) =
+        import(&server, "", "application/json", &file.to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!(server.reload().people["mia"].role, Role::Admin);
+}
+
+#[async_std::test]
+async fn replacing_keeps_the_owner() {
+    let server = household(&[Role::Owner, Role::Admin, Role::Member], |_| {});
+
+    let file = json!({
+        "people": [
+            { "name": "alice", "role": "admin" },
+            { "name": "mia", "role": "member" },
+        ],
+    });
+    let (status, reply) = import(
+        &server,
+        "&mode=replace",
+        "application/json",
+        &file.to_string(),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(
+        reply["errors"],
+        json!(["`olive` is the owner, so can't be left out"]),
+    );
+    assert_eq!(server.reload().people.len(), 3);
+}
+
+#[async_std::test]
+async fn an_active_admin_is_kept() {
+    let mut data = roles_data(&[
+        ("alice", 1, Role::Admin),
+        ("dan", 1, Role::Admin),
+        ("mia", 1, Role::Member),
+    ]);
+    data.people.get_mut("dan").unwrap().deactivated = true;
+    let server = TestServer::with_data(data, |_| {});
+
+    // dan's deactivated, so doesn't count
+    let file = json!({ "people": [{ "name": "alice", "role": "member" }] });
+    let (status, reply) =
+        import(&server, "", "application/json", &file.to_string()).await;
+    assert_eq!(status, 422);
+    assert_eq!(
+        reply["errors"],
+        json!(["there'd be no owner or admin left"])
+    );
+
+    let file = json!({ "people": [{ "name": "mia", "role": "member" }] });
+    let (status, reply) = import(
+        &server,
+        "&mode=replace",
+        "application/json",
+        &file.to_string(),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(
+        reply["errors"],
+        json!(["there'd be no owner or admin left"])
+    );
+    assert_eq!(server.reload().people["alice"].role, Role::Admin);
+}